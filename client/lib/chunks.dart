@@ -1,3 +1,4 @@
+import 'dart:convert';
 import 'dart:js_interop';
 import 'dart:typed_data';
 
@@ -6,8 +7,6 @@ import 'package:web/helpers.dart';
 import 'rendering.dart';
 import 'state.dart';
 
-final directions = ['Right', 'Down', 'Left', 'Up'];
-
 (int, int) dirNormal(int? direction) {
   switch (direction) {
     case 0:
@@ -26,25 +25,35 @@ final directions = ['Right', 'Down', 'Left', 'Up'];
 class Cursor {
   int x;
   int y;
-  int direction;
-  Cursor(this.x, this.y, this.direction);
+  (int, int) delta;
+  Cursor(this.x, this.y, this.delta);
 }
 
 class Chunk {
   final int x;
   final int y;
-  Uint8List? cells;
+  Int32List? cells;
   final canvas = document.createElement('canvas') as CanvasElement;
   late int lastZoom;
   var cursors = <int, Cursor>{};
 
   Chunk(this.x, this.y);
 
-  Uint8List getCells() {
-    return cells ??= Uint8List(chunkWidth * chunkWidth)
+  Int32List getCells() {
+    return cells ??= Int32List(chunkWidth * chunkWidth)
       ..fillRange(0, chunkWidth * chunkWidth, 0x20);
   }
 
+  /// Decodes the cells of a ChunkData message, which are little-endian
+  /// 32-bit integers.
+  static Int32List decodeCells(String data) {
+    final bytes = ByteData.sublistView(base64.decode(data));
+    return Int32List.fromList([
+      for (var i = 0; i < bytes.lengthInBytes; i += 4)
+        bytes.getInt32(i, Endian.little)
+    ]);
+  }
+
   void paint() {
     final zoom = camera.zoom;
     final scaledChunkWidth = chunkWidth * zoom;
@@ -89,6 +98,14 @@ class Chunk {
           if (c == 0x20) {
             continue;
           }
+          // The atlas only has the first 256 characters
+          if (c < 0 || c > 0xFF) {
+            final valid = c <= 0x10FFFF && (c < 0xD800 || c > 0xDFFF);
+            context.fillStyle = 'rgb(20, 25, 33)' as JSString;
+            context.fillText(String.fromCharCode(valid ? c : 0xFFFD),
+                cellX + zoom / 2, cellY + zoom / 2);
+            continue;
+          }
           final atlasCellWidth = atlas.width ~/ 16;
           final atlasX = (c % 16) * atlasCellWidth;
           final atlasY = (c ~/ 16) * atlasCellWidth;
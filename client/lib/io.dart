@@ -91,21 +91,21 @@ void handleMessage(dynamic messageData) {
       }) {
     final chunk = chunkCache.chunks[(x as int, y as int)];
     if (chunk != null) {
-      chunk.cells = base64.decode(data);
+      chunk.cells = Chunk.decodeCells(data);
       chunk.cursors.clear();
       for (final entry in cursors.entries) {
         final cursor = entry.value;
-        final direction = directions.indexOf(cursor['direction']);
+        final delta = cursor['delta'];
         chunk.cursors[int.parse(entry.key)] =
-            Cursor(cursor['x'], cursor['y'], direction);
+            Cursor(cursor['x'], cursor['y'], (delta['dx'], delta['dy']));
       }
       dirtyChunks.add((x, y));
       queueRender();
     }
   } else if (messageData
       case {'Update': {'action': dynamic action, 'x': int x, 'y': int y}}) {
-    final chunkX = x ~/ chunkWidth;
-    final chunkY = y ~/ chunkWidth;
+    final chunkX = (x / chunkWidth).floor();
+    final chunkY = (y / chunkWidth).floor();
     final localX = x % chunkWidth;
     final localY = y % chunkWidth;
     if (action case {'UpdateCell': {'c': int c}}) {
@@ -115,37 +115,50 @@ void handleMessage(dynamic messageData) {
       queueRender();
     } else if (action
         case {
-          'SpawnCursor': {'id': int id, 'direction': String directionStr}
+          'SpawnCursor': {
+            'id': int id,
+            'delta': {'dx': int dx, 'dy': int dy}
+          }
         }) {
       final chunk = chunkCache.getChunk(chunkX, chunkY);
-      final direction = directions.indexOf(directionStr);
       final cursor = chunk.cursors
-          .putIfAbsent(id, () => Cursor(localX, localY, direction));
+          .putIfAbsent(id, () => Cursor(localX, localY, (dx, dy)));
       cursor.x = localX;
       cursor.y = localY;
-      cursor.direction = direction;
+      cursor.delta = (dx, dy);
       chunkCache.cursors[id] = (chunkX, chunkY);
       queueRender();
     } else if (action
         case {'MoveCursor': {'id': int id, 'to_x': int toX, 'to_y': int toY}}) {
-      final toChunkX = toX ~/ chunkWidth;
-      final toChunkY = toY ~/ chunkWidth;
+      final toChunkX = (toX / chunkWidth).floor();
+      final toChunkY = (toY / chunkWidth).floor();
       final chunk = chunkCache.getChunk(chunkX, chunkY);
       chunkCache.cursors[id] = (toChunkX, toChunkY);
       if (chunkX == toChunkX || chunkY == toChunkY) {
         final cursor = chunk.cursors.putIfAbsent(
-            id, () => Cursor(toX % chunkWidth, toY % chunkWidth, 0));
+            id, () => Cursor(toX % chunkWidth, toY % chunkWidth, (1, 0)));
         cursor.x = toX % chunkWidth;
         cursor.y = toY % chunkWidth;
       } else {
         chunk.cursors.remove(id);
         final newChunk = chunkCache.getChunk(toChunkX, toChunkY);
         final cursor = chunk.cursors.remove(id) ??
-            Cursor(toX % chunkWidth, toY % chunkWidth, 0);
+            Cursor(toX % chunkWidth, toY % chunkWidth, (1, 0));
         newChunk.cursors[id] = cursor;
       }
       queueRender();
-    } else if (action case {'DestroyCursor': {'id': int id}}) {
+    } else if (action
+        case {
+          'ChangeDelta': {
+            'id': int id,
+            'delta': {'dx': int dx, 'dy': int dy}
+          }
+        }) {
+      final chunk = chunkCache.getChunk(chunkX, chunkY);
+      chunk.cursors[id]?.delta = (dx, dy);
+    } else if (action
+        case {'DestroyCursor': {'id': int id, 'reason': String reason}}) {
+      print('Cursor $id destroyed: $reason');
       final pos = chunkCache.cursors.remove(id);
       if (pos != null) {
         final chunk = chunkCache.getChunk(pos.$1, pos.$2);
@@ -155,6 +168,8 @@ void handleMessage(dynamic messageData) {
     } else {
       print('Unknown action');
     }
+  } else if (messageData case {'Error': {'message': String message}}) {
+    setOutput(message);
   } else {
     print('Unknown message');
   }
@@ -1,15 +1,20 @@
 use crate::sim::step::Simulation;
+use crate::sim::store::{StoreError, WorldStore};
 use crate::sim::subscription::{Subscriber, SubscriptionManager};
-use crate::sim::{Cursor, Direction, Grid, GridUpdate, GridUpdateAction};
+use crate::sim::vfs::{Quota, Vfs, VfsError};
+use crate::sim::{
+    Chunk, Cursor, Delta, Grid, GridUpdate, GridUpdateAction, INPUT_MESSAGE_LIMIT,
+    INPUT_QUEUE_LIMIT,
+};
 use anyhow::Result;
+use axum::body::Bytes;
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{ConnectInfo, State, WebSocketUpgrade};
-use axum::response::IntoResponse;
-use axum::routing::get;
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
+use axum::extract::{ConnectInfo, Path, State, WebSocketUpgrade};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::net::SocketAddr;
 use std::ops::Deref;
@@ -19,10 +24,20 @@ use tower_http::services::{ServeDir, ServeFile};
 
 mod sim;
 
+// Where the world's files are stored
+const DATABASE_PATH: &str = "world.db";
+/// How many ticks pass between attempts to page out chunks
+const PAGE_OUT_INTERVAL: usize = 100;
+
 pub struct AppState {
     pub tick_rate: Mutex<u64>,
     pub simulation: Mutex<Simulation>,
     pub subscription_manager: Mutex<SubscriptionManager<WebsocketSubscriber>>,
+    pub vfs: Vfs,
+    pub store: WorldStore,
+    /// Token that admin requests must carry, from `BEFUNGE_ADMIN_TOKEN`.
+    /// Without one, every admin request is refused.
+    pub admin_token: Option<String>,
 }
 
 pub async fn start_http_server(port: u16, state: Arc<AppState>) -> Result<()> {
@@ -32,6 +47,10 @@ pub async fn start_http_server(port: u16, state: Arc<AppState>) -> Result<()> {
 
     let router = axum::Router::new()
         .route("/ws", get(ws_handler))
+        .route("/files/:name", get(download_file).put(upload_file))
+        .route("/snapshots", get(list_snapshots))
+        .route("/snapshots/:name", put(take_snapshot))
+        .route("/snapshots/:name/restore", post(restore_snapshot))
         .fallback_service(
             ServeDir::new(client_build_dir).not_found_service(ServeFile::new(not_found_file)),
         )
@@ -51,40 +70,58 @@ pub async fn start_http_server(port: u16, state: Arc<AppState>) -> Result<()> {
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 enum BfMessage {
     ChunkData {
-        x: usize,
-        y: usize,
+        x: i64,
+        y: i64,
+        // Base64 of the chunk's cells, row by row, each as a little-endian
+        // 32-bit integer
         data: String,
         cursors: HashMap<usize, Cursor>,
     },
     Update(GridUpdate),
+    Output {
+        cursor: usize,
+        text: String,
+    },
+    Error {
+        message: String,
+    },
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 enum BfClientMessage {
-    SubscribeChunk { x: usize, y: usize },
-    UnsubscribeChunk { x: usize, y: usize },
+    SubscribeChunk { x: i64, y: i64 },
+    UnsubscribeChunk { x: i64, y: i64 },
+    SubscribeOutput { cursor: usize },
+    UnsubscribeOutput { cursor: usize },
+    SendInput { cursor: usize, text: String },
 }
 
 pub struct WebsocketSubscriber {
     tx: mpsc::Sender<String>,
 }
 
-impl Subscriber for WebsocketSubscriber {
-    fn notify(&self, updates: Vec<GridUpdate>) {
+impl WebsocketSubscriber {
+    fn send(&self, messages: &[BfMessage]) {
         self.tx
-            .try_send(
-                serde_json::to_string(
-                    &updates
-                        .into_iter()
-                        .map(|e| BfMessage::Update(e))
-                        .collect::<Vec<_>>(),
-                )
-                .unwrap(),
-            )
+            .try_send(serde_json::to_string(messages).unwrap())
             .unwrap();
     }
 }
 
+impl Subscriber for WebsocketSubscriber {
+    fn notify(&self, updates: Vec<GridUpdate>) {
+        self.send(
+            &updates
+                .into_iter()
+                .map(|update| match update.action {
+                    GridUpdateAction::Output { id, text } => BfMessage::Output { cursor: id, text },
+                    _ => BfMessage::Update(update),
+                })
+                .collect::<Vec<_>>(),
+        );
+    }
+}
+
 async fn ws_handler(
     state: State<Arc<AppState>>,
     ws: WebSocketUpgrade,
@@ -104,6 +141,170 @@ async fn ws_handler(
     })
 }
 
+fn vfs_error_response(e: VfsError) -> Response {
+    let status = match e {
+        VfsError::InvalidName => StatusCode::BAD_REQUEST,
+        VfsError::FileTooLarge | VfsError::QuotaExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+        VfsError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string()).into_response()
+}
+
+async fn download_file(state: State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    match state.vfs.read(&name) {
+        Ok(Some(data)) => data.into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => vfs_error_response(e),
+    }
+}
+
+async fn upload_file(
+    state: State<Arc<AppState>>,
+    Path(name): Path<String>,
+    data: Bytes,
+) -> Response {
+    match state.vfs.write(&name, &data) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => vfs_error_response(e),
+    }
+}
+
+/// Why input from a client can't be sent to `cursor`, if it can't. Input
+/// for cursors that are gone is dropped without an error, since they may
+/// have halted while it was on its way.
+fn input_error(grid: &Grid, cursor: usize, text: &str) -> Option<String> {
+    let queued = grid
+        .get_cursor(cursor)
+        .map_or(0, |cursor| cursor.input.len());
+    let length = text.chars().count();
+    if length > INPUT_MESSAGE_LIMIT {
+        Some(format!(
+            "input is longer than {} characters",
+            INPUT_MESSAGE_LIMIT
+        ))
+    } else if queued + length > INPUT_QUEUE_LIMIT {
+        Some(format!("cursor {}'s input is full", cursor))
+    } else {
+        None
+    }
+}
+
+/// Whether the request carries the admin token as `Authorization: Bearer
+/// <token>`. The token is compared in constant time.
+fn is_admin(headers: &HeaderMap, admin_token: Option<&str>) -> bool {
+    let Some(admin_token) = admin_token else {
+        return false;
+    };
+    let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    token.len() == admin_token.len()
+        && token
+            .bytes()
+            .zip(admin_token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn store_error_response(e: StoreError) -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+}
+
+async fn list_snapshots(state: State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if !is_admin(&headers, state.admin_token.as_deref()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match state.store.snapshot_names() {
+        Ok(names) => axum::Json(names).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn take_snapshot(
+    state: State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    if !is_admin(&headers, state.admin_token.as_deref()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let snapshot = state.simulation.lock().await.snapshot();
+    match state.store.save_snapshot(&name, &snapshot) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn restore_snapshot(
+    state: State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    if !is_admin(&headers, state.admin_token.as_deref()) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let snapshot = match state.store.load_snapshot(&name) {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return store_error_response(e),
+    };
+    // Lock the simulation before the subscription manager, in the same order
+    // as the tick task
+    let mut simulation = state.simulation.lock().await;
+    let mut subscription_manager = state.subscription_manager.lock().await;
+    simulation.restore(snapshot);
+    println!(
+        "Restored snapshot {:?} at tick {}",
+        name, simulation.grid.ticks
+    );
+    // Subscribers start over from the restored chunks, including empty ones
+    // for chunks the snapshot doesn't have
+    let mut messages: BTreeMap<usize, Vec<BfMessage>> = BTreeMap::new();
+    for (&(x, y), subscribers) in &subscription_manager.chunks {
+        let empty = Chunk::new();
+        let chunk = simulation.grid.chunks.get(&(x, y)).unwrap_or(&empty);
+        let message = BfMessage::ChunkData {
+            x,
+            y,
+            data: chunk.encode_cells(),
+            cursors: chunk.cursors.clone(),
+        };
+        for subscriber in subscribers {
+            messages
+                .entry(*subscriber)
+                .or_default()
+                .push(message.clone());
+        }
+    }
+    // The ids of cursors the snapshot doesn't have will be handed out again,
+    // so following their output ends here
+    let gone: Vec<usize> = subscription_manager
+        .cursors
+        .keys()
+        .filter(|cursor| !simulation.grid.cursor_chunks.contains_key(cursor))
+        .copied()
+        .collect();
+    for cursor in gone {
+        let message = BfMessage::Error {
+            message: format!("cursor {} is gone after restoring {:?}", cursor, name),
+        };
+        for subscriber in subscription_manager.remove_cursor(cursor) {
+            messages
+                .entry(subscriber)
+                .or_default()
+                .push(message.clone());
+        }
+    }
+    for (id, messages) in messages {
+        subscription_manager.subscribers[id].2.send(&messages);
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
 async fn handle_client_message(
     socket: &mut WebSocket,
     message: BfClientMessage,
@@ -112,12 +313,28 @@ async fn handle_client_message(
 ) {
     match message {
         BfClientMessage::SubscribeChunk { x, y } => {
+            // Lock the simulation before the subscription manager, in the same
+            // order as the tick task
+            let mut simulation = state.simulation.lock().await;
+            if !simulation.grid.config.contains_chunk(x, y) {
+                socket
+                    .send(Message::Text(
+                        serde_json::to_string(&BfMessage::Error {
+                            message: format!("chunk {},{} is outside the world", x, y),
+                        })
+                        .unwrap(),
+                    ))
+                    .await
+                    .unwrap();
+                return;
+            }
             let mut subscription_manager = state.subscription_manager.lock().await;
             subscription_manager.subscribe_chunks(id, vec![(x, y)]);
+            // Subscribed chunks stay in memory until they are unsubscribed
+            simulation.grid.page_in(x, y);
             // Send the current state of the chunk to the client
-            let simulation = state.simulation.lock().await;
             if let Some(chunk) = simulation.grid.chunks.get(&(x, y)) {
-                let data = BASE64_STANDARD.encode(&chunk.cells);
+                let data = chunk.encode_cells();
                 socket
                     .send(Message::Text(
                         serde_json::to_string(&BfMessage::ChunkData {
@@ -136,6 +353,33 @@ async fn handle_client_message(
             let mut subscription_manager = state.subscription_manager.lock().await;
             subscription_manager.unsubscribe_chunk(id, x, y);
         }
+        BfClientMessage::SubscribeOutput { cursor } => {
+            let mut subscription_manager = state.subscription_manager.lock().await;
+            subscription_manager.subscribe_cursor(id, cursor);
+        }
+        BfClientMessage::UnsubscribeOutput { cursor } => {
+            let mut subscription_manager = state.subscription_manager.lock().await;
+            subscription_manager.unsubscribe_cursor(id, cursor);
+        }
+        BfClientMessage::SendInput { cursor, text } => {
+            let mut simulation = state.simulation.lock().await;
+            if let Some(message) = input_error(&simulation.grid, cursor, &text) {
+                socket
+                    .send(Message::Text(
+                        serde_json::to_string(&BfMessage::Error { message }).unwrap(),
+                    ))
+                    .await
+                    .unwrap();
+                return;
+            }
+            if let Some((x, y)) = simulation.grid.get_cursor_position(cursor) {
+                simulation.grid.apply(GridUpdate {
+                    x,
+                    y,
+                    action: GridUpdateAction::AppendInput { id: cursor, text },
+                });
+            }
+        }
     }
 }
 
@@ -152,13 +396,10 @@ async fn handle_socket(
                 match msg {
                     None => break,
                     Some(msg) => {
-                        match msg? {
-                            Message::Text(s) => {
-                                println!("Received message from {:?}: {}", who, s);
-                                let message: BfClientMessage = serde_json::from_str(&s)?;
-                                handle_client_message(&mut socket, message, id, state.clone()).await;
-                            }
-                            _ => {},
+                        if let Message::Text(s) = msg? {
+                            println!("Received message from {:?}: {}", who, s);
+                            let message: BfClientMessage = serde_json::from_str(&s)?;
+                            handle_client_message(&mut socket, message, id, state.clone()).await;
                         }
                     }
                 }
@@ -173,25 +414,59 @@ async fn handle_socket(
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let grid = Grid::new_from_string(include_str!("examples/foo.txt"));
-    let mut simulation = Simulation::new(grid);
-    simulation.grid.apply(GridUpdate {
+/// Creates the world the server starts with when none has been saved yet.
+fn new_world() -> Result<Grid> {
+    let mut grid = Grid::new_from_string(include_str!("examples/foo.txt"));
+    // Runs can be reproduced by starting again with the same seed
+    grid.config.seed = match env::var("BEFUNGE_SEED") {
+        Ok(seed) => seed.parse()?,
+        Err(_) => rand::random(),
+    };
+    let id = grid.allocate_cursor_id();
+    grid.apply(GridUpdate {
         x: 0,
         y: 0,
         action: GridUpdateAction::SpawnCursor {
-            id: 0,
-            direction: Direction::Right,
+            id,
+            delta: Delta::RIGHT,
             stack: vec![],
+            stack_stack: vec![],
+            storage_offset: (0, 0),
+            semantics: BTreeMap::new(),
             energy: 1000,
             string_mode: false,
+            priority: 0,
         },
     });
+    Ok(grid)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let db = sled::open(DATABASE_PATH)?;
+    let vfs = Vfs::open(&db, Quota::default())?;
+    let store = WorldStore::open(&db)?;
+    let grid = match store.load()? {
+        Some(grid) => {
+            println!("Loaded world at tick {}", grid.ticks);
+            grid
+        }
+        None => {
+            let mut grid = new_world()?;
+            grid.pager = Some(store.clone());
+            grid
+        }
+    };
+    println!("World seed: {}", grid.config.seed);
+    let mut simulation = Simulation::new(grid);
+    simulation.vfs = Some(vfs.clone());
     let app_state = Arc::new(AppState {
         tick_rate: Mutex::new(1000),
         simulation: Mutex::new(simulation),
         subscription_manager: Mutex::new(SubscriptionManager::new()),
+        vfs,
+        store,
+        admin_token: env::var("BEFUNGE_ADMIN_TOKEN").ok(),
     });
 
     // Start a background thread that ticks the simulation
@@ -205,6 +480,17 @@ async fn main() -> Result<()> {
             let mut simulation = app_state_clone.simulation.lock().await;
             let updates = simulation.step();
             let subscription_manager = app_state_clone.subscription_manager.lock().await;
+            match app_state_clone.store.save(&mut simulation.grid) {
+                // Chunks nobody is looking at can go once they are saved
+                Ok(()) if simulation.grid.ticks % PAGE_OUT_INTERVAL == 0 => {
+                    let subscribed = &subscription_manager.chunks;
+                    simulation
+                        .grid
+                        .page_out(|chunk| subscribed.contains_key(chunk));
+                }
+                Ok(()) => {}
+                Err(e) => eprintln!("Failed to save the world: {}", e),
+            }
             subscription_manager.notify(updates);
         }
     });
@@ -214,3 +500,108 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::{send_input, simulate, temporary_db};
+    use axum::http::HeaderValue;
+
+    fn test_state(admin_token: Option<&str>) -> Arc<AppState> {
+        let db = temporary_db();
+        Arc::new(AppState {
+            tick_rate: Mutex::new(1000),
+            simulation: Mutex::new(simulate("@", 0)),
+            subscription_manager: Mutex::new(SubscriptionManager::new()),
+            vfs: Vfs::open(&db, Quota::default()).unwrap(),
+            store: WorldStore::open(&db).unwrap(),
+            admin_token: admin_token.map(str::to_string),
+        })
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = HeaderValue::from_str(&format!("Bearer {}", token)).unwrap();
+        headers.insert(header::AUTHORIZATION, value);
+        headers
+    }
+
+    #[test]
+    fn input_is_checked_against_the_limits() {
+        let mut simulation = simulate("~", 0);
+        assert_eq!(input_error(&simulation.grid, 0, "abc"), None);
+        let long = "a".repeat(INPUT_MESSAGE_LIMIT + 1);
+        assert!(input_error(&simulation.grid, 0, &long).is_some());
+
+        let message = "a".repeat(INPUT_MESSAGE_LIMIT);
+        for _ in 0..INPUT_QUEUE_LIMIT / INPUT_MESSAGE_LIMIT {
+            send_input(&mut simulation, &message);
+        }
+        assert!(input_error(&simulation.grid, 0, "a").is_some());
+    }
+
+    #[test]
+    fn input_for_missing_cursors_is_not_an_error() {
+        // The cursor halts, so its id no longer exists
+        let simulation = simulate("@", 1);
+        assert!(simulation.grid.get_cursor(0).is_none());
+        assert_eq!(input_error(&simulation.grid, 0, "abc"), None);
+        assert_eq!(input_error(&simulation.grid, 42, "abc"), None);
+    }
+
+    #[tokio::test]
+    async fn admin_requests_need_the_token() {
+        let state = test_state(Some("secret"));
+        let name = || Path("start".to_string());
+        for headers in [HeaderMap::new(), bearer("wrong"), bearer("secre")] {
+            let response = take_snapshot(State(state.clone()), headers.clone(), name()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = restore_snapshot(State(state.clone()), headers.clone(), name()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = list_snapshots(State(state.clone()), headers).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(state.store.snapshot_names().unwrap().is_empty());
+
+        let response = take_snapshot(State(state.clone()), bearer("secret"), name()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(state.store.snapshot_names().unwrap(), vec!["start"]);
+    }
+
+    #[tokio::test]
+    async fn admin_requests_are_refused_without_a_token() {
+        let state = test_state(None);
+        let response = list_snapshots(State(state), bearer("")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn restoring_ends_output_of_missing_cursors() {
+        let state = test_state(Some("secret"));
+        let name = || Path("start".to_string());
+        let response = take_snapshot(State(state.clone()), bearer("secret"), name()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let (tx, mut rx) = mpsc::channel(100);
+        let mut subscription_manager = state.subscription_manager.lock().await;
+        let id = subscription_manager.subscribe(WebsocketSubscriber { tx });
+        subscription_manager.subscribe_cursor(id, 0);
+        // A cursor spawned after the snapshot
+        subscription_manager.subscribe_cursor(id, 1);
+        drop(subscription_manager);
+
+        let response = restore_snapshot(State(state.clone()), bearer("secret"), name()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let subscription_manager = state.subscription_manager.lock().await;
+        assert!(subscription_manager.cursors.contains_key(&0));
+        assert!(!subscription_manager.cursors.contains_key(&1));
+        assert_eq!(subscription_manager.subscribers[id].1.len(), 1);
+        let messages: Vec<BfMessage> = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(
+            messages,
+            vec![BfMessage::Error {
+                message: "cursor 1 is gone after restoring \"start\"".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::sim::fingerprint::{Context, Fingerprint};
+
+/// A timer for each cursor. Time in the world is measured in ticks, so
+/// that programs behave the same however fast the server runs them.
+pub struct Hrti;
+
+impl Fingerprint for Hrti {
+    fn name(&self) -> &'static str {
+        "HRTI"
+    }
+
+    fn instructions(&self) -> &'static str {
+        "EGMST"
+    }
+
+    fn execute(&self, instruction: u8, context: &mut Context) {
+        let ticks = context.grid.ticks as i64;
+        match instruction {
+            // The timer can't measure anything shorter than a tick
+            b'G' => context.update_stack(0, vec![1]),
+            b'M' => context.set_state(vec![ticks]),
+            b'T' => match context.state().first() {
+                Some(mark) => context.update_stack(0, vec![ticks - mark]),
+                None => context.reflect(),
+            },
+            b'E' => context.set_state(vec![]),
+            // Ticks are indivisible, so no time has passed within one
+            _ => context.update_stack(0, vec![0]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sim::testing::{delta_after, run};
+    use crate::sim::Delta;
+
+    #[test]
+    fn hrti_fingerprint_counts_ticks() {
+        assert_eq!(run(r#""HRTI"4(M  T"#, 12)[2..], [3]);
+        assert_eq!(delta_after(r#""HRTI"4(T"#, 9), Delta::LEFT);
+    }
+}
@@ -0,0 +1,242 @@
+mod hrti;
+mod modu;
+mod null;
+mod roma;
+mod strn;
+
+use crate::sim::{Cursor, Delta, Grid, GridUpdate, GridUpdateAction, Rules};
+use std::collections::HashMap;
+
+/// A Befunge-98 fingerprint: a set of instructions bound to the letters `A`
+/// to `Z` while the fingerprint is loaded on a cursor with `(`.
+pub trait Fingerprint: Send + Sync {
+    /// The name `(` and `)` identify the fingerprint by, such as `"ROMA"`
+    fn name(&self) -> &'static str;
+
+    /// The letters this fingerprint defines instructions for
+    fn instructions(&self) -> &'static str;
+
+    /// Executes the instruction bound to `instruction` for the cursor in
+    /// `context`.
+    fn execute(&self, instruction: u8, context: &mut Context);
+}
+
+/// Returns the id of a fingerprint with the given name, as `(` and `)`
+/// compute it from the characters on the stack.
+pub fn fingerprint_id(name: &str) -> i64 {
+    name.bytes()
+        .fold(0, |id: i64, c| id.wrapping_mul(256).wrapping_add(c as i64))
+}
+
+/// The fingerprints a simulation can load, by id.
+#[derive(Default)]
+pub struct FingerprintRegistry {
+    fingerprints: HashMap<i64, Box<dyn Fingerprint>>,
+}
+
+impl FingerprintRegistry {
+    pub fn new() -> FingerprintRegistry {
+        FingerprintRegistry::default()
+    }
+
+    /// A registry with the fingerprints that ship with the server.
+    pub fn standard() -> FingerprintRegistry {
+        let mut registry = FingerprintRegistry::new();
+        registry.register(Box::new(hrti::Hrti));
+        registry.register(Box::new(modu::Modu));
+        registry.register(Box::new(null::Null));
+        registry.register(Box::new(roma::Roma));
+        registry.register(Box::new(strn::Strn));
+        registry
+    }
+
+    /// Adds a fingerprint, replacing any with the same name.
+    pub fn register(&mut self, fingerprint: Box<dyn Fingerprint>) {
+        debug_assert!(fingerprint
+            .instructions()
+            .bytes()
+            .all(|c| c.is_ascii_uppercase()));
+        self.fingerprints
+            .insert(fingerprint_id(fingerprint.name()), fingerprint);
+    }
+
+    pub fn get(&self, id: i64) -> Option<&dyn Fingerprint> {
+        self.fingerprints.get(&id).map(|f| f.as_ref())
+    }
+}
+
+/// What a fingerprint instruction can see and do. Like the built-in
+/// instructions, it reads the grid as it was at the start of the tick and
+/// records its effects as updates.
+pub struct Context<'a> {
+    pub id: usize,
+    // Absolute position of the cursor
+    pub x: i64,
+    pub y: i64,
+    pub cursor: &'a Cursor,
+    pub rules: &'a Rules,
+    pub grid: &'a Grid,
+    // The id of the fingerprint being executed
+    pub fingerprint: i64,
+    // The delta the cursor moves by after the instruction
+    pub delta: Delta,
+    pub(crate) waiting: bool,
+    updates: &'a mut Vec<GridUpdate>,
+}
+
+impl<'a> Context<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        id: usize,
+        x: i64,
+        y: i64,
+        cursor: &'a Cursor,
+        rules: &'a Rules,
+        grid: &'a Grid,
+        fingerprint: i64,
+        updates: &'a mut Vec<GridUpdate>,
+    ) -> Context<'a> {
+        Context {
+            id,
+            x,
+            y,
+            cursor,
+            rules,
+            grid,
+            fingerprint,
+            delta: cursor.delta,
+            waiting: false,
+            updates,
+        }
+    }
+
+    fn push(&mut self, action: GridUpdateAction) {
+        self.updates.push(GridUpdate {
+            x: self.x,
+            y: self.y,
+            action,
+        });
+    }
+
+    pub fn peek(&self, depth: usize) -> i64 {
+        self.cursor.peek(depth)
+    }
+
+    pub fn peek_string(&self, depth: usize) -> (String, usize) {
+        self.cursor.peek_string(depth)
+    }
+
+    pub fn update_stack(&mut self, pop: usize, push: Vec<i64>) {
+        self.push(GridUpdateAction::UpdateStack {
+            id: self.id,
+            stack: 0,
+            pop,
+            push,
+        });
+    }
+
+    /// Reverses the cursor, which is how instructions report failure.
+    pub fn reflect(&mut self) {
+        self.delta = self.delta.reverse();
+        self.push(GridUpdateAction::ChangeDelta {
+            id: self.id,
+            delta: self.delta,
+        });
+    }
+
+    pub fn output(&mut self, text: String) {
+        self.push(GridUpdateAction::Output { id: self.id, text });
+    }
+
+    pub fn consume_input(&mut self, count: usize) {
+        self.push(GridUpdateAction::ConsumeInput { id: self.id, count });
+    }
+
+    /// Makes the cursor wait in place and try the instruction again next
+    /// tick. Any updates made by the instruction are discarded.
+    pub fn wait(&mut self) {
+        self.waiting = true;
+    }
+
+    /// Reads a cell like `g`, with coordinates relative to the storage
+    /// offset.
+    pub fn get_cell(&self, x: i64, y: i64) -> i64 {
+        match self.rules.resolve(self.cursor.storage_offset, x, y) {
+            Some((x, y)) => self.grid.get_cell(x, y) as i64,
+            None => b' ' as i64,
+        }
+    }
+
+    /// Writes a cell like `p`, with coordinates relative to the storage
+    /// offset.
+    pub fn set_cell(&mut self, x: i64, y: i64, value: i64) {
+        if let Some((x, y)) = self.rules.resolve(self.cursor.storage_offset, x, y) {
+            self.updates.push(GridUpdate {
+                x,
+                y,
+                action: GridUpdateAction::UpdateCell { c: value as i32 },
+            });
+        }
+    }
+
+    /// State the fingerprint keeps for this cursor, empty if it has none.
+    pub fn state(&self) -> &[i64] {
+        self.cursor
+            .fingerprint_state
+            .get(&self.fingerprint)
+            .map_or(&[], |state| state.as_slice())
+    }
+
+    pub fn set_state(&mut self, state: Vec<i64>) {
+        self.push(GridUpdateAction::SetFingerprintState {
+            id: self.id,
+            fingerprint: self.fingerprint,
+            state,
+        });
+    }
+}
+
+/// Converts a cell value to the character `,` would print for it.
+pub fn cell_char(value: i64) -> char {
+    u32::try_from(value)
+        .ok()
+        .and_then(char::from_u32)
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The stack entries for pushing `text` as a 0gnirts string.
+pub fn string_cells(text: &str) -> Vec<i64> {
+    std::iter::once(0)
+        .chain(text.chars().rev().map(|c| c as i64))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sim::fingerprint::fingerprint_id;
+    use crate::sim::testing::{delta_after, run, simulate};
+    use crate::sim::Delta;
+
+    #[test]
+    fn load_fingerprint() {
+        let roma = fingerprint_id("ROMA");
+        assert_eq!(run(r#""ROMA"4(XVI"#, 11), vec![roma, 1, 10, 5, 1]);
+
+        // Unknown fingerprints and letters without a fingerprint reflect
+        assert_eq!(delta_after(r#""ABCD"4("#, 8), Delta::LEFT);
+        assert_eq!(delta_after("X", 1), Delta::LEFT);
+    }
+
+    #[test]
+    fn unload_fingerprint_restores_letters() {
+        let simulation = simulate(r#""ROMA"4("NULL"4(X"#, 17);
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().delta, Delta::LEFT);
+
+        let roma = fingerprint_id("ROMA");
+        let simulation = simulate(r#""ROMA"4("NULL"4("NULL"4)X"#, 25);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        let null = fingerprint_id("NULL");
+        assert_eq!(cursor.stack, vec![roma, 1, null, 1, 10]);
+        assert!(cursor.semantics.values().all(|ids| ids == &vec![roma]));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::sim::fingerprint::{Context, Fingerprint};
+
+/// Modulo arithmetic with different sign conventions. Like `%`, the
+/// remainder of a division by zero is 0.
+pub struct Modu;
+
+impl Fingerprint for Modu {
+    fn name(&self) -> &'static str {
+        "MODU"
+    }
+
+    fn instructions(&self) -> &'static str {
+        "MRU"
+    }
+
+    fn execute(&self, instruction: u8, context: &mut Context) {
+        let b = context.peek(0);
+        let a = context.peek(1);
+        let value = if b == 0 {
+            0
+        } else {
+            let remainder = a.wrapping_rem(b);
+            match instruction {
+                // The result has the sign of the divisor
+                b'M' if remainder != 0 && (remainder < 0) != (b < 0) => remainder + b,
+                // Sam Holden's unsigned remainder
+                b'U' => remainder.wrapping_abs(),
+                // C's remainder, which has the sign of the dividend
+                _ => remainder,
+            }
+        };
+        context.update_stack(2, vec![value]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sim::testing::run;
+
+    #[test]
+    fn modu_fingerprint() {
+        assert_eq!(run(r#""MODU"4(07-3M"#, 13)[2..], [2]);
+        assert_eq!(run(r#""MODU"4(07-3R"#, 13)[2..], [-1]);
+        assert_eq!(run(r#""MODU"4(07-3U"#, 13)[2..], [1]);
+        assert_eq!(run(r#""MODU"4(70M"#, 11)[2..], [0]);
+    }
+}
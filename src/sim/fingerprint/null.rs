@@ -0,0 +1,18 @@
+use crate::sim::fingerprint::{Context, Fingerprint};
+
+/// Makes every letter reflect, hiding any fingerprints loaded before it.
+pub struct Null;
+
+impl Fingerprint for Null {
+    fn name(&self) -> &'static str {
+        "NULL"
+    }
+
+    fn instructions(&self) -> &'static str {
+        "ABCDEFGHIJKLMNOPQRSTUVWXYZ"
+    }
+
+    fn execute(&self, _instruction: u8, context: &mut Context) {
+        context.reflect();
+    }
+}
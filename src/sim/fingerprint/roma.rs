@@ -0,0 +1,27 @@
+use crate::sim::fingerprint::{Context, Fingerprint};
+
+/// Roman numerals: each letter pushes its value.
+pub struct Roma;
+
+impl Fingerprint for Roma {
+    fn name(&self) -> &'static str {
+        "ROMA"
+    }
+
+    fn instructions(&self) -> &'static str {
+        "CDILMVX"
+    }
+
+    fn execute(&self, instruction: u8, context: &mut Context) {
+        let value = match instruction {
+            b'I' => 1,
+            b'V' => 5,
+            b'X' => 10,
+            b'L' => 50,
+            b'C' => 100,
+            b'D' => 500,
+            _ => 1000,
+        };
+        context.update_stack(0, vec![value]);
+    }
+}
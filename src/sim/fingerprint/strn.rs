@@ -0,0 +1,140 @@
+use crate::sim::fingerprint::{cell_char, string_cells, Context, Fingerprint};
+
+/// Most cells `G` reads before giving up on finding the end of a string
+const MAX_STRING_LENGTH: usize = 4096;
+
+/// Operations on 0gnirts strings, which are pushed with their first
+/// character on top and a 0 below the last.
+pub struct Strn;
+
+impl Fingerprint for Strn {
+    fn name(&self) -> &'static str {
+        "STRN"
+    }
+
+    fn instructions(&self) -> &'static str {
+        "ACDFGILMNPRSV"
+    }
+
+    fn execute(&self, instruction: u8, context: &mut Context) {
+        match instruction {
+            // Appends the second string to the top one
+            b'A' => {
+                let (top, top_len) = context.peek_string(0);
+                let (second, second_len) = context.peek_string(top_len);
+                context.update_stack(top_len + second_len, string_cells(&(top + &second)));
+            }
+            b'C' => {
+                let (top, top_len) = context.peek_string(0);
+                let (second, second_len) = context.peek_string(top_len);
+                let order = top.cmp(&second) as i64;
+                context.update_stack(top_len + second_len, vec![order]);
+            }
+            b'D' => {
+                let (text, len) = context.peek_string(0);
+                context.update_stack(len, vec![]);
+                context.output(text);
+            }
+            // Finds the second string in the top one, and pushes the rest of
+            // the top string from there, or an empty string
+            b'F' => {
+                let (top, top_len) = context.peek_string(0);
+                let (second, second_len) = context.peek_string(top_len);
+                let found = top.find(&second).map_or("", |i| &top[i..]);
+                context.update_stack(top_len + second_len, string_cells(found));
+            }
+            b'G' => {
+                let y = context.peek(0);
+                let x = context.peek(1);
+                let text = (0..MAX_STRING_LENGTH as i64)
+                    .map(|i| context.get_cell(x.wrapping_add(i), y))
+                    .take_while(|value| *value != 0)
+                    .map(cell_char)
+                    .collect::<String>();
+                context.update_stack(2, string_cells(&text));
+            }
+            // Reads a line of input, without the newline
+            b'I' => {
+                let Some(end) = context.cursor.input.iter().position(|c| *c == '\n') else {
+                    context.wait();
+                    return;
+                };
+                let text = context.cursor.input.iter().take(end).collect::<String>();
+                context.consume_input(end + 1);
+                context.update_stack(0, string_cells(&text));
+            }
+            b'L' | b'R' => {
+                let n = context.peek(0).max(0) as usize;
+                let (text, len) = context.peek_string(1);
+                let count = text.chars().count();
+                let part = if instruction == b'L' {
+                    text.chars().take(n).collect::<String>()
+                } else {
+                    text.chars().skip(count.saturating_sub(n)).collect()
+                };
+                context.update_stack(len + 1, string_cells(&part));
+            }
+            b'M' => {
+                let n = context.peek(0).max(0) as usize;
+                let start = context.peek(1).max(0) as usize;
+                let (text, len) = context.peek_string(2);
+                let part = text.chars().skip(start).take(n).collect::<String>();
+                context.update_stack(len + 2, string_cells(&part));
+            }
+            b'N' => {
+                let (text, _) = context.peek_string(0);
+                context.update_stack(0, vec![text.chars().count() as i64]);
+            }
+            // Writes the string and its terminating 0 to the grid
+            b'P' => {
+                let y = context.peek(0);
+                let x = context.peek(1);
+                let (text, len) = context.peek_string(2);
+                context.update_stack(len + 2, vec![]);
+                for (i, c) in text.chars().chain(['\0']).enumerate() {
+                    context.set_cell(x.wrapping_add(i as i64), y, c as i64);
+                }
+            }
+            b'S' => {
+                let value = context.peek(0);
+                context.update_stack(1, string_cells(&value.to_string()));
+            }
+            // Parses a leading decimal number, or pushes 0
+            _ => {
+                let (text, len) = context.peek_string(0);
+                let text = text.trim_start();
+                let end = text
+                    .char_indices()
+                    .find(|(i, c)| !(c.is_ascii_digit() || (*i == 0 && (*c == '-' || *c == '+'))))
+                    .map_or(text.len(), |(i, _)| i);
+                let value = text[..end].parse::<i64>().unwrap_or(0);
+                context.update_stack(len, vec![value]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sim::testing::{collect_output, push_i64_max, run, simulate};
+
+    #[test]
+    fn strn_fingerprint() {
+        let mut simulation = simulate(r#""STRN"4(0"olleh"D@"#, 0);
+        assert_eq!(collect_output(&mut simulation, 30), "hello");
+
+        assert_eq!(run(r#""STRN"4(0"24-"V"#, 15)[2..], [-42]);
+        assert_eq!(run(r#""STRN"4(0"cba"N"#, 15)[2..], [0, 99, 98, 97, 3]);
+        assert_eq!(run(r#""STRN"4(0"dc"0"ba"A"#, 19)[2..], [0, 100, 99, 98, 97]);
+    }
+
+    #[test]
+    fn strn_coordinates_wrap() {
+        let get = format!(r#""STRN"4({}0G"#, push_i64_max());
+        let stack = run(&get, 139);
+        assert_eq!(stack[2], 0);
+        assert!(stack[3..].iter().all(|c| *c == b' ' as i64));
+        let put = format!(r#""STRN"4(0"a"{}0P"#, push_i64_max());
+        assert_eq!(run(&put, 143).len(), 2);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::sim::instructions::{befunge98, Instruction, InstructionSet, Outcome};
+use crate::sim::step::SimulationStep;
+use crate::sim::{GridUpdate, GridUpdateAction};
+use std::collections::VecDeque;
+
+/// Everything the Befunge-93 reference interpreter understands. Other
+/// instructions are no-ops in the Befunge-93 dialect.
+const BEFUNGE93_INSTRUCTIONS: &[u8] = b"0123456789+-*/%!`><^v?_|\":\\$.,#gp&~@ ";
+
+/// Reads a number the way the Befunge-93 reference interpreter does with
+/// `scanf("%d")`: whitespace is skipped, and if something other than a number
+/// follows, 0 is read and that character is left in the queue.
+fn scan_number(input: &VecDeque<char>) -> Option<(i64, usize)> {
+    let start = input.iter().position(|c| !c.is_whitespace())?;
+    let negative = input[start] == '-';
+    let digits = if negative || input[start] == '+' {
+        start + 1
+    } else {
+        start
+    };
+    let mut value: i64 = 0;
+    for (i, c) in input.iter().enumerate().skip(digits) {
+        match c.to_digit(10) {
+            Some(digit) => value = value.wrapping_mul(10).wrapping_add(digit as i64),
+            None if i == digits => return Some((0, start)),
+            None => return Some((if negative { -value } else { value }, i)),
+        }
+    }
+    None
+}
+
+/// The instructions of the Befunge-93 reference interpreter. Most behave as
+/// in Befunge-98; the ones that differ are handled here.
+pub struct Befunge93;
+
+impl InstructionSet for Befunge93 {
+    fn execute(&self, step: &mut SimulationStep, instruction: &Instruction) -> Outcome {
+        // The reference interpreter only keeps the low byte of a cell
+        let instruction = &Instruction {
+            c: instruction.c as u8 as i32,
+            ..*instruction
+        };
+        let Instruction {
+            id,
+            x,
+            y,
+            cursor,
+            rules,
+            c,
+        } = *instruction;
+        let push = match c as u8 {
+            c if !BEFUNGE93_INSTRUCTIONS.contains(&c) => return Outcome::Unknown,
+            b'g' => {
+                let position = rules.resolve(cursor.storage_offset, cursor.peek(1), cursor.peek(0));
+                // The reference interpreter stores cells as signed chars, and
+                // reads 0 outside the playfield
+                let value = match position {
+                    Some((x, y)) => step.grid.get_cell(x, y) as i8 as i64,
+                    None => 0,
+                };
+                (2, value)
+            }
+            b'&' => {
+                // Without input the cursor waits in place
+                let Some((value, count)) = scan_number(&cursor.input) else {
+                    return Outcome::Stay;
+                };
+                step.updates.push(GridUpdate {
+                    x,
+                    y,
+                    action: GridUpdateAction::ConsumeInput { id, count },
+                });
+                (0, value)
+            }
+            // The reference interpreter asks the user for the result of a
+            // division by zero
+            b'/' | b'%' if cursor.peek(0) == 0 => {
+                let Some((value, count)) = scan_number(&cursor.input) else {
+                    return Outcome::Stay;
+                };
+                step.updates.push(GridUpdate {
+                    x,
+                    y,
+                    action: GridUpdateAction::ConsumeInput { id, count },
+                });
+                (2, value)
+            }
+            _ => return befunge98::execute(step, instruction),
+        };
+
+        let (pop, value) = push;
+        step.updates.push(GridUpdate {
+            x,
+            y,
+            action: GridUpdateAction::UpdateStack {
+                id,
+                stack: 0,
+                pop,
+                push: vec![value],
+            },
+        });
+        Outcome::Move {
+            delta: cursor.delta,
+            distance: 1,
+        }
+    }
+}
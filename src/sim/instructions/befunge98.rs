@@ -0,0 +1,1191 @@
+use crate::sim::fingerprint::{cell_char, fingerprint_id, Context};
+use crate::sim::instructions::{Instruction, InstructionSet, Outcome};
+use crate::sim::step::SimulationStep;
+use crate::sim::{
+    join_coordinate, Cursor, Delta, DestroyReason, Grid, GridUpdate, GridUpdateAction, Rules,
+    CHUNK_WIDTH, STACK_TRANSFER_LIMIT,
+};
+use rand::Rng;
+use std::collections::VecDeque;
+
+/// The instructions of Befunge-98. Anything else reflects, as the
+/// specification asks.
+pub struct Befunge98;
+
+impl InstructionSet for Befunge98 {
+    fn execute(&self, step: &mut SimulationStep, instruction: &Instruction) -> Outcome {
+        match execute(step, instruction) {
+            Outcome::Unknown => {
+                let delta = instruction.cursor.delta.reverse();
+                step.updates.push(GridUpdate {
+                    x: instruction.x,
+                    y: instruction.y,
+                    action: GridUpdateAction::ChangeDelta {
+                        id: instruction.id,
+                        delta,
+                    },
+                });
+                Outcome::Move { delta, distance: 1 }
+            }
+            outcome => outcome,
+        }
+    }
+}
+
+/// Reads a decimal number from the front of an input queue for `&`. Anything
+/// before the first digit is skipped, and a `-` directly in front of it makes
+/// the number negative. Returns the number and how many characters it used,
+/// or `None` while the number may still be incomplete.
+fn read_number(input: &VecDeque<char>) -> Option<(i64, usize)> {
+    let start = input.iter().position(|c| c.is_ascii_digit())?;
+    let negative = start > 0 && input[start - 1] == '-';
+    let mut value: i64 = 0;
+    for (i, c) in input.iter().enumerate().skip(start) {
+        match c.to_digit(10) {
+            Some(digit) => value = value.wrapping_mul(10).wrapping_add(digit as i64),
+            None => return Some((if negative { -value } else { value }, i)),
+        }
+    }
+    // More digits may still arrive
+    None
+}
+
+/// How many cells `k` and `;` look ahead along a cursor's path
+const SCAN_LIMIT: i64 = 4096;
+
+/// Follows `delta` from `(x, y)` until `found` accepts a cell, and returns
+/// that cell and how many steps away it is. Gives up at an edge that doesn't
+/// wrap, or after `SCAN_LIMIT` steps.
+fn scan<F: FnMut(i32) -> bool>(
+    grid: &Grid,
+    rules: &Rules,
+    mut x: i64,
+    mut y: i64,
+    delta: Delta,
+    mut found: F,
+) -> Option<(i32, i64)> {
+    for steps in 1..=SCAN_LIMIT {
+        (x, y) = rules.advance(x, y, delta)?;
+        let c = grid.get_cell(x, y);
+        if found(c) {
+            return Some((c, steps));
+        }
+    }
+    None
+}
+
+/// Identifies this interpreter in `y`, packed like a fingerprint name
+pub(crate) const HANDPRINT: &str = "BNGS";
+
+/// The interpreter version reported by `y`, such as 100 for 0.1.0
+fn version() -> i64 {
+    env!("CARGO_PKG_VERSION")
+        .split('.')
+        .take(3)
+        .fold(0, |version, part| {
+            version * 100 + part.parse::<i64>().unwrap_or(0)
+        })
+}
+
+/// Pops `b` then `a` off the cursor's stack and pushes `op(a, b)`.
+fn binary_op<F: FnOnce(i64, i64) -> i64>(
+    step: &mut SimulationStep,
+    id: usize,
+    x: i64,
+    y: i64,
+    cursor: &Cursor,
+    op: F,
+) {
+    let b = cursor.peek(0);
+    let a = cursor.peek(1);
+    step.updates.push(GridUpdate {
+        x,
+        y,
+        action: GridUpdateAction::UpdateStack {
+            id,
+            stack: 0,
+            pop: 2,
+            push: vec![op(a, b)],
+        },
+    });
+}
+
+/// Executes `k`: the next instruction along the cursor's path runs as
+/// many times as the popped count, each run costing one energy. The
+/// cursor then moves past that instruction, unless it changed the
+/// cursor's delta, in which case the cursor moves from the `k`.
+fn iterate(step: &mut SimulationStep, instruction: &Instruction) -> Outcome {
+    let Instruction {
+        id,
+        x: abs_x,
+        y: abs_y,
+        cursor,
+        rules,
+        ..
+    } = *instruction;
+    let start = step.updates.len();
+    let count = cursor.peek(0);
+    let pop = GridUpdateAction::UpdateStack {
+        id,
+        stack: 0,
+        pop: 1,
+        push: vec![],
+    };
+    // Later runs see the effects of earlier ones on this copy
+    let mut local = cursor.clone();
+    local.apply(pop.clone());
+    step.updates.push(GridUpdate {
+        x: abs_x,
+        y: abs_y,
+        action: pop,
+    });
+
+    let mut comment = false;
+    let found = scan(step.grid, rules, abs_x, abs_y, cursor.delta, |c| {
+        if c == b';' as i32 {
+            comment = !comment;
+        }
+        !comment && c != b' ' as i32 && c != b';' as i32
+    });
+    let Some((c, steps)) = found else {
+        return Outcome::Move {
+            delta: cursor.delta,
+            distance: 1,
+        };
+    };
+    // A count of 0 skips the instruction, and `k` can't iterate itself
+    let iterates_itself = c == b'k' as i32;
+    if count <= 0 || iterates_itself {
+        return Outcome::Move {
+            delta: cursor.delta,
+            distance: if iterates_itself { steps } else { steps + 1 },
+        };
+    }
+
+    let (mut delta, mut distance) = (cursor.delta, 1);
+    for _ in 0..count {
+        if local.energy == 0 {
+            break;
+        }
+        let before = step.updates.len();
+        let run = Instruction {
+            cursor: &local,
+            c,
+            ..*instruction
+        };
+        let Outcome::Move {
+            delta: next_delta,
+            distance: next_distance,
+        } = step.execute(&run)
+        else {
+            // Unless the instruction halted the cursor, it is waiting for
+            // input and the whole `k` runs again next tick
+            let halted = step.updates[before..]
+                .iter()
+                .any(|update| matches!(update.action, GridUpdateAction::DestroyCursor { .. }));
+            if !halted {
+                step.updates.truncate(start);
+            }
+            return Outcome::Stay;
+        };
+        (delta, distance) = (next_delta, next_distance);
+        let spend = GridUpdateAction::ConsumeEnergy { id, energy: 1 };
+        step.updates.push(GridUpdate {
+            x: abs_x,
+            y: abs_y,
+            action: spend,
+        });
+        for update in &step.updates[before..] {
+            local.apply(update.action.clone());
+        }
+    }
+
+    if local.energy == 0 {
+        step.updates.push(GridUpdate {
+            x: abs_x,
+            y: abs_y,
+            action: GridUpdateAction::DestroyCursor {
+                id,
+                reason: DestroyReason::OutOfEnergy,
+            },
+        });
+        return Outcome::Stay;
+    }
+    if delta != cursor.delta {
+        Outcome::Move { delta, distance }
+    } else {
+        Outcome::Move {
+            delta,
+            distance: steps + distance,
+        }
+    }
+}
+
+/// Returns everything `y` reports, with the cell pushed first, which ends
+/// up deepest, first. From the top, these are the Befunge-98 cells:
+/// flags, bytes per cell, handprint, version, paradigm, path separator,
+/// dimensions, cursor id, team, position, delta, storage offset, the
+/// corners of the world or region, date, time, the number of stacks and
+/// the size of each, the arguments and the environment. Below them are
+/// cells specific to this world: the cursor's remaining energy, the
+/// number of live cursors and the tick count. The world has no calendar,
+/// so the date and time are always 0.
+fn system_info(step: &SimulationStep, instruction: &Instruction) -> Vec<i64> {
+    let Instruction {
+        id,
+        x: abs_x,
+        y: abs_y,
+        cursor,
+        rules,
+        ..
+    } = *instruction;
+    let grid = step.grid;
+    let (origin_x, origin_y) = rules.origin;
+    // The least and greatest cells of the world. Without bounds, those are
+    // the corners of the chunks that exist.
+    let ((least_x, least_y), (greatest_x, greatest_y)) = match rules.bounds {
+        Some(bounds) => (
+            (bounds.min_x, bounds.min_y),
+            (
+                bounds.max_x.saturating_sub(1),
+                bounds.max_y.saturating_sub(1),
+            ),
+        ),
+        None => {
+            let mut least = (i64::MAX, i64::MAX);
+            let mut greatest = (i64::MIN, i64::MIN);
+            for (chunk_x, chunk_y) in grid.chunks.keys().chain(&grid.paged_out) {
+                least.0 = least.0.min(join_coordinate(*chunk_x, 0));
+                least.1 = least.1.min(join_coordinate(*chunk_y, 0));
+                greatest.0 = greatest.0.max(join_coordinate(*chunk_x, CHUNK_WIDTH - 1));
+                greatest.1 = greatest.1.max(join_coordinate(*chunk_y, CHUNK_WIDTH - 1));
+            }
+            (least, greatest)
+        }
+    };
+    // Stack sizes don't include the count `y` pops
+    let mut stack_sizes = vec![cursor.stack.len().saturating_sub(1) as i64];
+    stack_sizes.extend(cursor.stack_stack.iter().rev().map(|s| s.len() as i64));
+
+    let mut info = vec![
+        grid.ticks as i64,
+        grid.cursor_chunks.len() as i64,
+        cursor.energy as i64,
+        // No environment variables, and no arguments
+        0,
+        0,
+        0,
+    ];
+    info.extend(stack_sizes.iter().rev());
+    info.extend([
+        stack_sizes.len() as i64,
+        0,
+        0,
+        // Like the rest of Funge arithmetic, these wrap when the world is
+        // wider than an `i64` can count, so adding them up still gives the
+        // greatest cell
+        greatest_x.wrapping_sub(least_x),
+        greatest_y.wrapping_sub(least_y),
+        least_x.wrapping_sub(origin_x),
+        least_y.wrapping_sub(origin_y),
+        cursor.storage_offset.0,
+        cursor.storage_offset.1,
+        cursor.delta.dx,
+        cursor.delta.dy,
+        abs_x.wrapping_sub(origin_x),
+        abs_y.wrapping_sub(origin_y),
+        0,
+        id as i64,
+        2,
+        b'/' as i64,
+        0,
+        version(),
+        fingerprint_id(HANDPRINT),
+        // Cells hold `i32`s, even though stacks hold `i64`s
+        size_of::<i32>() as i64,
+        // `t` is always implemented, and `i` and `o` need a filesystem
+        if step.vfs.is_some() { 0b111 } else { 0b001 },
+    ]);
+    info
+}
+
+/// Executes `i`, which loads a file into the grid at a position relative
+/// to the storage offset. In binary mode, when bit 0 of the flags is set,
+/// the whole file goes on one line. Returns `false` if the file can't be
+/// read.
+fn input_file(step: &mut SimulationStep, instruction: &Instruction) -> bool {
+    let Instruction {
+        id,
+        x: abs_x,
+        y: abs_y,
+        cursor,
+        rules,
+        ..
+    } = *instruction;
+    let (name, len) = cursor.peek_string(0);
+    let flags = cursor.peek(len);
+    let y = cursor.peek(len + 1);
+    let x = cursor.peek(len + 2);
+    let Some(Ok(Some(data))) = step.vfs.map(|vfs| vfs.read(&name)) else {
+        return false;
+    };
+    let lines = if flags & 1 == 1 {
+        vec![data.as_slice()]
+    } else {
+        data.split(|c| *c == b'\n').collect()
+    };
+    let mut width = 0;
+    let mut height = 0;
+    for (dy, line) in lines.iter().enumerate() {
+        let line = line.iter().filter(|c| flags & 1 == 1 || **c != b'\r');
+        for (dx, c) in line.enumerate() {
+            let Some((to_x, to_y)) = rules.resolve(
+                cursor.storage_offset,
+                x.wrapping_add(dx as i64),
+                y.wrapping_add(dy as i64),
+            ) else {
+                continue;
+            };
+            step.updates.push(GridUpdate {
+                x: to_x,
+                y: to_y,
+                action: GridUpdateAction::UpdateCell { c: *c as i32 },
+            });
+            width = width.max(dx as i64 + 1);
+            height = height.max(dy as i64 + 1);
+        }
+    }
+    step.updates.push(GridUpdate {
+        x: abs_x,
+        y: abs_y,
+        action: GridUpdateAction::UpdateStack {
+            id,
+            stack: 0,
+            pop: len + 3,
+            push: vec![width, height, x, y],
+        },
+    });
+    true
+}
+
+/// Executes `o`, which saves a rectangle of the grid to a file. In text
+/// mode, when bit 0 of the flags is set, trailing spaces and empty lines
+/// are left out. The file is written when the tick is applied. Returns
+/// `false` if the file can't be written.
+fn output_file(step: &mut SimulationStep, instruction: &Instruction) -> bool {
+    let Instruction {
+        id,
+        x: abs_x,
+        y: abs_y,
+        cursor,
+        rules,
+        ..
+    } = *instruction;
+    let (name, len) = cursor.peek_string(0);
+    let flags = cursor.peek(len);
+    let y = cursor.peek(len + 1);
+    let x = cursor.peek(len + 2);
+    let height = cursor.peek(len + 3);
+    let width = cursor.peek(len + 4);
+    let Some(vfs) = step.vfs else {
+        return false;
+    };
+    // Rectangles that can't fit in a file aren't read at all. Each line
+    // ends with a newline.
+    let size = height.checked_mul(width.saturating_add(1));
+    if width < 0 || height < 0 || size.is_none_or(|size| size as usize > vfs.quota().file_size) {
+        return false;
+    }
+
+    let mut lines = (0..height)
+        .map(|dy| {
+            let mut line = (0..width)
+                .map(|dx| {
+                    match rules.resolve(
+                        cursor.storage_offset,
+                        x.wrapping_add(dx),
+                        y.wrapping_add(dy),
+                    ) {
+                        // Files hold bytes, so only the low byte of each
+                        // cell is saved
+                        Some((x, y)) => step.grid.get_cell(x, y) as u8,
+                        None => b' ',
+                    }
+                })
+                .collect::<Vec<u8>>();
+            if flags & 1 == 1 {
+                line.truncate(line.iter().rposition(|c| *c != b' ').map_or(0, |i| i + 1));
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+    if flags & 1 == 1 {
+        lines.truncate(
+            lines
+                .iter()
+                .rposition(|line| !line.is_empty())
+                .map_or(0, |i| i + 1),
+        );
+    }
+    let mut data = vec![];
+    for line in lines {
+        data.extend(line);
+        data.push(b'\n');
+    }
+    if vfs.check_write(&name, data.len()).is_err() {
+        return false;
+    }
+    step.updates.push(GridUpdate {
+        x: abs_x,
+        y: abs_y,
+        action: GridUpdateAction::WriteFile { id, name, data },
+    });
+    step.updates.push(GridUpdate {
+        x: abs_x,
+        y: abs_y,
+        action: GridUpdateAction::UpdateStack {
+            id,
+            stack: 0,
+            pop: len + 5,
+            push: vec![],
+        },
+    });
+    true
+}
+
+/// Executes a Befunge-98 instruction, or returns `Outcome::Unknown` without
+/// doing anything if it isn't one. The other dialects build on this.
+pub(crate) fn execute(step: &mut SimulationStep, instruction: &Instruction) -> Outcome {
+    let Instruction {
+        id,
+        x: abs_x,
+        y: abs_y,
+        cursor,
+        rules,
+        c,
+    } = *instruction;
+    // Cells too wide to be a character aren't instructions
+    let Ok(c) = u8::try_from(c) else {
+        return Outcome::Unknown;
+    };
+    let grid = step.grid;
+    let mut delta = cursor.delta;
+    let mut distance = 1;
+
+    match c {
+        b'"' => step.updates.push(GridUpdate {
+            x: abs_x,
+            y: abs_y,
+            action: GridUpdateAction::ToggleStringMode { id },
+        }),
+        b'^' => {
+            delta = Delta::UP;
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'v' => {
+            delta = Delta::DOWN;
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'<' => {
+            delta = Delta::LEFT;
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'>' => {
+            delta = Delta::RIGHT;
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'?' => {
+            delta = [Delta::UP, Delta::DOWN, Delta::LEFT, Delta::RIGHT][step.rng.gen_range(0..4)];
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'[' | b']' | b'r' => {
+            delta = match c {
+                b'[' => delta.turn_left(),
+                b']' => delta.turn_right(),
+                _ => delta.reverse(),
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'x' => {
+            delta = Delta {
+                dx: cursor.peek(1),
+                dy: cursor.peek(0),
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 2,
+                    push: vec![],
+                },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'w' => {
+            let b = cursor.peek(0);
+            let a = cursor.peek(1);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 2,
+                    push: vec![],
+                },
+            });
+            if a != b {
+                delta = if a < b {
+                    delta.turn_left()
+                } else {
+                    delta.turn_right()
+                };
+                step.updates.push(GridUpdate {
+                    x: abs_x,
+                    y: abs_y,
+                    action: GridUpdateAction::ChangeDelta { id, delta },
+                });
+            }
+        }
+        b'j' => {
+            distance = cursor.peek(0).wrapping_add(1);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+        }
+        b'+' => binary_op(step, id, abs_x, abs_y, cursor, |a, b| a.wrapping_add(b)),
+        b'-' => binary_op(step, id, abs_x, abs_y, cursor, |a, b| a.wrapping_sub(b)),
+        b'*' => binary_op(step, id, abs_x, abs_y, cursor, |a, b| a.wrapping_mul(b)),
+        b'_' | b'|' => {
+            let value = cursor.peek(0);
+            delta = match (c, value == 0) {
+                (b'_', true) => Delta::RIGHT,
+                (b'_', false) => Delta::LEFT,
+                (_, true) => Delta::DOWN,
+                (_, false) => Delta::UP,
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'!' => {
+            let value = cursor.peek(0);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![(value == 0) as i64],
+                },
+            });
+        }
+        b'`' => binary_op(step, id, abs_x, abs_y, cursor, |a, b| (a > b) as i64),
+        b'g' => {
+            let position = rules.resolve(cursor.storage_offset, cursor.peek(1), cursor.peek(0));
+            let value = match position {
+                Some((x, y)) => grid.get_cell(x, y) as i64,
+                None => b' ' as i64,
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 2,
+                    push: vec![value],
+                },
+            });
+        }
+        b'p' => {
+            let position = rules.resolve(cursor.storage_offset, cursor.peek(1), cursor.peek(0));
+            let value = cursor.peek(2);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 3,
+                    push: vec![],
+                },
+            });
+            // The cell update is positioned at the target so that it
+            // reaches subscribers of the chunk being written to. Writes
+            // outside the world or region are dropped.
+            if let Some((x, y)) = position {
+                step.updates.push(GridUpdate {
+                    x,
+                    y,
+                    action: GridUpdateAction::UpdateCell { c: value as i32 },
+                });
+            }
+        }
+        b'0'..=b'9' => {
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 0,
+                    push: vec![(c - b'0') as i64],
+                },
+            });
+        }
+        b'a'..=b'f' => {
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 0,
+                    push: vec![(c - b'a' + 10) as i64],
+                },
+            });
+        }
+        b':' => {
+            let value = cursor.peek(0);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![value, value],
+                },
+            });
+        }
+        b'\\' => {
+            let b = cursor.peek(0);
+            let a = cursor.peek(1);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 2,
+                    push: vec![b, a],
+                },
+            });
+        }
+        b'$' => {
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+        }
+        b'.' | b',' => {
+            let value = cursor.peek(0);
+            let text = if c == b'.' {
+                format!("{} ", value)
+            } else {
+                cell_char(value).to_string()
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::Output { id, text },
+            });
+        }
+        b'&' | b'~' => {
+            let read = if c == b'&' {
+                read_number(&cursor.input)
+            } else {
+                cursor.input.front().map(|c| (*c as i64, 1))
+            };
+            // Without input the cursor waits in place, and spends no
+            // energy doing so
+            let Some((value, count)) = read else {
+                return Outcome::Stay;
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ConsumeInput { id, count },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 0,
+                    push: vec![value],
+                },
+            });
+        }
+        b't' => {
+            // The child starts one cell behind the parent so that it
+            // doesn't split again on its first tick. Without room
+            // behind the parent, no child is spawned.
+            let child_pos = rules.advance(abs_x, abs_y, delta.reverse());
+            if let Some((child_x, child_y)) = child_pos {
+                let child_id = step.next_cursor_id;
+                step.next_cursor_id += 1;
+                // The parent gives half of its energy to the child
+                let energy = cursor.energy / 2;
+                step.updates.push(GridUpdate {
+                    x: abs_x,
+                    y: abs_y,
+                    action: GridUpdateAction::ConsumeEnergy { id, energy },
+                });
+                step.updates.push(GridUpdate {
+                    x: child_x,
+                    y: child_y,
+                    action: GridUpdateAction::SpawnCursor {
+                        id: child_id,
+                        delta: delta.reverse(),
+                        stack: cursor.stack.clone(),
+                        stack_stack: cursor.stack_stack.clone(),
+                        storage_offset: cursor.storage_offset,
+                        semantics: cursor.semantics.clone(),
+                        energy,
+                        string_mode: false,
+                        priority: cursor.priority,
+                    },
+                });
+            }
+        }
+        b'{' => {
+            let count = cursor.peek(0);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+            // Storage offsets are relative to the region, like `g`
+            // and `p` coordinates
+            let storage_offset = (
+                abs_x.wrapping_add(delta.dx) - rules.origin.0,
+                abs_y.wrapping_add(delta.dy) - rules.origin.1,
+            );
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::BeginBlock {
+                    id,
+                    count,
+                    storage_offset,
+                },
+            });
+        }
+        // Without a second stack, `}` and `u` act like `r`
+        b'}' | b'u' if cursor.stack_stack.is_empty() => {
+            delta = delta.reverse();
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::ChangeDelta { id, delta },
+            });
+        }
+        b'}' => {
+            let count = cursor.peek(0);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::EndBlock { id, count },
+            });
+        }
+        b'u' => {
+            // Moves values one at a time, so they end up reversed
+            let count = cursor.peek(0);
+            let n = (count.unsigned_abs() as usize).min(STACK_TRANSFER_LIMIT);
+            let (from, to, values) = if count > 0 {
+                let values = (0..n).map(|i| cursor.peek_second(i).unwrap());
+                (1, 0, values.collect())
+            } else {
+                (0, 1, (1..=n).map(|i| cursor.peek(i)).collect())
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: from,
+                    pop: n,
+                    push: vec![],
+                },
+            });
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: to,
+                    pop: 0,
+                    push: values,
+                },
+            });
+        }
+        b'k' => return iterate(step, instruction),
+        b';' => {
+            // Everything up to the next `;` is skipped in one tick
+            match scan(grid, rules, abs_x, abs_y, delta, |c| c == b';' as i32) {
+                Some((_, steps)) => distance = steps + 1,
+                None => {
+                    delta = delta.reverse();
+                    step.updates.push(GridUpdate {
+                        x: abs_x,
+                        y: abs_y,
+                        action: GridUpdateAction::ChangeDelta { id, delta },
+                    });
+                }
+            }
+        }
+        b'\'' => {
+            let value = match rules.advance(abs_x, abs_y, delta) {
+                Some((x, y)) => grid.get_cell(x, y) as i64,
+                None => b' ' as i64,
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 0,
+                    push: vec![value],
+                },
+            });
+            distance = 2;
+        }
+        b's' => {
+            let value = cursor.peek(0);
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push: vec![],
+                },
+            });
+            if let Some((x, y)) = rules.advance(abs_x, abs_y, delta) {
+                step.updates.push(GridUpdate {
+                    x,
+                    y,
+                    action: GridUpdateAction::UpdateCell { c: value as i32 },
+                });
+            }
+            distance = 2;
+        }
+        b'(' | b')' => {
+            // The fingerprint's name is packed into one number, with
+            // its first character in the highest byte
+            let count = (cursor.peek(0).max(0) as usize).min(STACK_TRANSFER_LIMIT);
+            let fingerprint = (1..=count).rev().fold(0, |id: i64, i| {
+                id.wrapping_mul(256).wrapping_add(cursor.peek(i))
+            });
+            let loaded = step.fingerprints.get(fingerprint);
+            let push = match (c, loaded) {
+                (b'(', Some(_)) => vec![fingerprint, 1],
+                _ => vec![],
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: count + 1,
+                    push,
+                },
+            });
+            let action = match (c, loaded) {
+                (_, None) => {
+                    delta = delta.reverse();
+                    GridUpdateAction::ChangeDelta { id, delta }
+                }
+                (b'(', Some(loaded)) => GridUpdateAction::LoadSemantics {
+                    id,
+                    fingerprint,
+                    instructions: loaded.instructions().to_string(),
+                },
+                (_, Some(loaded)) => GridUpdateAction::UnloadSemantics {
+                    id,
+                    instructions: loaded.instructions().to_string(),
+                },
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action,
+            });
+        }
+        b'A'..=b'Z' => {
+            let fingerprint = cursor
+                .semantics
+                .get(&(c as char))
+                .and_then(|ids| ids.last())
+                .and_then(|id| Some((*id, step.fingerprints.get(*id)?)));
+            // Letters without a fingerprint loaded reflect
+            let Some((fingerprint_id, fingerprint)) = fingerprint else {
+                delta = delta.reverse();
+                step.updates.push(GridUpdate {
+                    x: abs_x,
+                    y: abs_y,
+                    action: GridUpdateAction::ChangeDelta { id, delta },
+                });
+                return Outcome::Move { delta, distance };
+            };
+            let start = step.updates.len();
+            let mut context = Context::new(
+                id,
+                abs_x,
+                abs_y,
+                cursor,
+                rules,
+                grid,
+                fingerprint_id,
+                &mut step.updates,
+            );
+            fingerprint.execute(c, &mut context);
+            if context.waiting {
+                step.updates.truncate(start);
+                return Outcome::Stay;
+            }
+            delta = context.delta;
+        }
+        b'y' => {
+            let n = cursor.peek(0);
+            let info = system_info(step, instruction);
+            // A positive count picks one cell, as if everything had
+            // been pushed. Past the end of the information, that
+            // reaches into the stack below it.
+            let push = match usize::try_from(n) {
+                Ok(0) | Err(_) => info,
+                Ok(n) if n <= info.len() => vec![info[info.len() - n]],
+                Ok(n) => vec![cursor.peek(n - info.len())],
+            };
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 1,
+                    push,
+                },
+            });
+        }
+        // Like other failing instructions, `i` and `o` reflect when
+        // the file can't be read or written
+        b'i' | b'o' => {
+            let done = if c == b'i' {
+                input_file(step, instruction)
+            } else {
+                output_file(step, instruction)
+            };
+            if !done {
+                delta = delta.reverse();
+                step.updates.push(GridUpdate {
+                    x: abs_x,
+                    y: abs_y,
+                    action: GridUpdateAction::ChangeDelta { id, delta },
+                });
+            }
+        }
+        b'#' => distance = 2,
+        b'@' => {
+            step.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::DestroyCursor {
+                    id,
+                    reason: DestroyReason::Halted,
+                },
+            });
+            return Outcome::Stay;
+        }
+        // Division and remainder by zero push 0
+        b'/' => binary_op(step, id, abs_x, abs_y, cursor, |a, b| {
+            if b == 0 {
+                0
+            } else {
+                a.wrapping_div(b)
+            }
+        }),
+        b'%' => binary_op(step, id, abs_x, abs_y, cursor, |a, b| {
+            if b == 0 {
+                0
+            } else {
+                a.wrapping_rem(b)
+            }
+        }),
+        _ => return Outcome::Unknown,
+    }
+
+    Outcome::Move { delta, distance }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sim::fingerprint::fingerprint_id;
+    use crate::sim::instructions::befunge98::HANDPRINT;
+    use crate::sim::testing::{collect_output, run, simulate, simulate_grid, simulate_with_config};
+    use crate::sim::{Bounds, Dialect, Grid, WorldConfig};
+
+    #[test]
+    fn befunge98_reflects_unknown_instructions() {
+        let config = WorldConfig {
+            dialect: Dialect::Befunge98,
+            ..WorldConfig::default()
+        };
+        let mut simulation = simulate_with_config("#@.1m2.@", config, 0);
+        assert_eq!(collect_output(&mut simulation, 20), "0 1 ");
+
+        // The world dialect passes over them
+        let mut simulation = simulate("#@.1m2.@", 0);
+        assert_eq!(collect_output(&mut simulation, 20), "0 2 ");
+    }
+
+    #[test]
+    fn system_info_cells() {
+        assert_eq!(run("1y", 2), vec![1]);
+        assert_eq!(run("2y", 2), vec![4]);
+        assert_eq!(run("3y", 2), vec![fingerprint_id(HANDPRINT)]);
+        assert_eq!(run("8y", 2), vec![0]);
+        // The position vector has y on top, like all vectors
+        assert_eq!(run("ay", 2), vec![0]);
+        assert_eq!(run("  by", 4), vec![3]);
+        // The world is 10 chunks of 32 cells wide and high
+        assert_eq!(run("92*y", 4), vec![319]);
+        // Past the end of the information, `y` picks from the stack
+        assert_eq!(run("7ff+y", 5), vec![7, 7]);
+    }
+
+    #[test]
+    fn system_info_pushes_everything_for_zero() {
+        let stack = run("0y", 2);
+        assert_eq!(stack.len(), 29);
+        // World cells first, then the environment, arguments and stack sizes
+        assert_eq!(stack[..9], [1, 1, 999, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(stack.last(), Some(&1));
+    }
+
+    /// Runs `0y` in `grid` and returns the size and least point of the
+    /// world it reports.
+    fn reported_extent(grid: Grid) -> Vec<i64> {
+        let simulation = simulate_grid(grid, (0, 0), 2);
+        let stack = &simulation.grid.get_cursor(0).unwrap().stack;
+        stack[10..14].to_vec()
+    }
+
+    #[test]
+    fn system_info_reports_far_out_worlds() {
+        let mut grid = Grid::new_from_string("0y");
+        grid.config = WorldConfig {
+            bounds: None,
+            ..WorldConfig::default()
+        };
+        grid.set_cell(i64::MAX, 0, b'x' as i32);
+        assert_eq!(reported_extent(grid.clone()), vec![i64::MAX, 31, 0, 0]);
+
+        // The world is wider than an `i64` can count, so its size wraps
+        grid.set_cell(i64::MIN, 5, b'x' as i32);
+        let extent = reported_extent(grid);
+        assert_eq!(extent, vec![-1, 31, i64::MIN, 0]);
+        assert_eq!(extent[2].wrapping_add(extent[0]), i64::MAX);
+
+        let mut grid = Grid::new_from_string("0y");
+        grid.config.bounds = Bounds::new(i64::MIN, 0, i64::MAX, 1);
+        assert_eq!(reported_extent(grid), vec![-2, 0, i64::MIN, 0]);
+    }
+}
@@ -0,0 +1,110 @@
+pub mod befunge93;
+pub mod befunge98;
+mod world;
+
+use crate::sim::step::SimulationStep;
+use crate::sim::{Cursor, Delta, Dialect, Rules};
+use std::collections::HashMap;
+
+/// The opcodes of a dialect. Each cursor runs its instructions through the
+/// set registered for the dialect it is in, so a world or a single region
+/// can try out new instructions without touching the others.
+pub trait InstructionSet: Send + Sync {
+    /// Executes `instruction`, recording its effects as updates on `step`.
+    fn execute(&self, step: &mut SimulationStep, instruction: &Instruction) -> Outcome;
+}
+
+/// An instruction a cursor is about to execute.
+#[derive(Clone, Copy)]
+pub struct Instruction<'a> {
+    pub id: usize,
+    // Absolute position of the cursor
+    pub x: i64,
+    pub y: i64,
+    pub cursor: &'a Cursor,
+    pub rules: &'a Rules,
+    // The cell under the cursor
+    pub c: i32,
+}
+
+/// What happens to a cursor after an instruction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The cursor moves `distance` steps along `delta`
+    Move { delta: Delta, distance: i64 },
+    /// The cursor stays where it is this tick, because it is waiting or was
+    /// destroyed
+    Stay,
+    /// The set doesn't define the instruction and did nothing
+    Unknown,
+}
+
+/// The instruction set of each dialect.
+#[derive(Default)]
+pub struct InstructionSets {
+    sets: HashMap<Dialect, Box<dyn InstructionSet>>,
+}
+
+impl InstructionSets {
+    pub fn new() -> InstructionSets {
+        InstructionSets::default()
+    }
+
+    /// The instruction sets that ship with the server, one per dialect.
+    pub fn standard() -> InstructionSets {
+        let mut sets = InstructionSets::new();
+        sets.register(Dialect::World, Box::new(world::World));
+        sets.register(Dialect::Befunge93, Box::new(befunge93::Befunge93));
+        sets.register(Dialect::Befunge98, Box::new(befunge98::Befunge98));
+        sets
+    }
+
+    /// Sets the instructions of a dialect, replacing any it had.
+    pub fn register(&mut self, dialect: Dialect, set: Box<dyn InstructionSet>) {
+        self.sets.insert(dialect, set);
+    }
+
+    pub fn get(&self, dialect: Dialect) -> Option<&dyn InstructionSet> {
+        self.sets.get(&dialect).map(|set| set.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::{collect_output, simulate};
+    use crate::sim::{GridUpdate, GridUpdateAction};
+
+    #[test]
+    fn custom_instruction_set() {
+        struct Answer;
+
+        impl InstructionSet for Answer {
+            fn execute(&self, step: &mut SimulationStep, instruction: &Instruction) -> Outcome {
+                if instruction.c != b'm' as i32 {
+                    return befunge98::execute(step, instruction);
+                }
+                step.updates.push(GridUpdate {
+                    x: instruction.x,
+                    y: instruction.y,
+                    action: GridUpdateAction::UpdateStack {
+                        id: instruction.id,
+                        stack: 0,
+                        pop: 0,
+                        push: vec![42],
+                    },
+                });
+                Outcome::Move {
+                    delta: instruction.cursor.delta,
+                    distance: 1,
+                }
+            }
+        }
+
+        let mut simulation = simulate("m.@", 0);
+        simulation
+            .instruction_sets
+            .register(Dialect::World, Box::new(Answer));
+        assert_eq!(collect_output(&mut simulation, 10), "42 ");
+    }
+}
@@ -0,0 +1,12 @@
+use crate::sim::instructions::{befunge98, Instruction, InstructionSet, Outcome};
+use crate::sim::step::SimulationStep;
+
+/// The instructions of the shared world: those of Befunge-98, with anything
+/// else left undefined so that cursors pass over it.
+pub struct World;
+
+impl InstructionSet for World {
+    fn execute(&self, step: &mut SimulationStep, instruction: &Instruction) -> Outcome {
+        befunge98::execute(step, instruction)
+    }
+}
@@ -1,103 +1,695 @@
+pub mod fingerprint;
+pub mod instructions;
 pub mod step;
+pub mod store;
 pub mod subscription;
+pub mod vfs;
 
+#[cfg(test)]
+pub(crate) mod testing;
+
+use crate::sim::fingerprint::cell_char;
+use crate::sim::store::WorldStore;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::Display;
 
 const CHUNK_WIDTH: usize = 32;
 const CHUNK_LIMIT: usize = 10; // 335544320;
+/// Most values `{`, `}` and `u` move between stacks at once. Funge-98 puts
+/// no limit on the count, so this deliberately departs from the spec to
+/// keep one instruction from filling the server's memory with zeros.
+const STACK_TRANSFER_LIMIT: usize = 1024;
+/// Longest piece of input a client can send a cursor at once, in characters
+pub const INPUT_MESSAGE_LIMIT: usize = 1024;
+/// Most characters that can wait in a cursor's input. Input past this is
+/// dropped.
+pub const INPUT_QUEUE_LIMIT: usize = 4096;
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Cursor {
     // Position is relative to the chunk
     pub x: usize,
     pub y: usize,
-    pub direction: Direction,
+    pub delta: Delta,
     pub stack: Vec<i64>,
     pub energy: usize,
     pub string_mode: bool,
+    // The stacks under the top one, which is `stack`. The last one is the
+    // second on the stack of stacks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stack_stack: Vec<Vec<i64>>,
+    // Added to `g` and `p` coordinates, and set by `{` and `}`
+    #[serde(default, skip_serializing_if = "is_origin")]
+    pub storage_offset: (i64, i64),
+    // The ids of the fingerprints loaded for each letter, with the one in
+    // effect last
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub semantics: BTreeMap<char, Vec<i64>>,
+    // State kept by fingerprints, by fingerprint id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fingerprint_state: BTreeMap<i64, Vec<i64>>,
+    // Text sent by clients that has not been read by `&` or `~` yet
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub input: VecDeque<char>,
+    // Cursors with a higher priority act first in each tick
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i64,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Chunk {
-    pub cells: [u8; CHUNK_WIDTH * CHUNK_WIDTH],
+    pub cells: [i32; CHUNK_WIDTH * CHUNK_WIDTH],
     pub cursors: HashMap<usize, Cursor>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Grid {
     pub ticks: usize,
-    pub chunks: HashMap<(usize, usize), Chunk>,
-    pub cursor_chunks: HashMap<usize, (usize, usize)>,
+    // The chunks in memory
+    pub chunks: HashMap<(i64, i64), Chunk>,
+    pub cursor_chunks: HashMap<usize, (i64, i64)>,
+    pub config: WorldConfig,
+    // Lowest id that no cursor has used yet
+    pub next_cursor_id: usize,
+    // Chunks changed since the world was last saved
+    pub dirty_chunks: HashSet<(i64, i64)>,
+    // Chunks that were dropped from memory and are only kept in `pager`
+    pub paged_out: HashSet<(i64, i64)>,
+    pub pager: Option<WorldStore>,
+    // Paged out chunks that have been read since they were paged out. Reads
+    // don't bring a chunk back into `chunks`, since they only borrow the grid.
+    paged_in: RefCell<HashMap<(i64, i64), Chunk>>,
+}
+
+/// Grids are equal if they hold the same world, whatever has been saved.
+impl PartialEq for Grid {
+    fn eq(&self, other: &Grid) -> bool {
+        self.ticks == other.ticks
+            && self.chunks == other.chunks
+            && self.cursor_chunks == other.cursor_chunks
+            && self.config == other.config
+            && self.next_cursor_id == other.next_cursor_id
+            && self.paged_out == other.paged_out
+    }
+}
+
+impl Eq for Grid {}
+
+/// A rectangle of cells, including the minimum and excluding the maximum
+/// coordinates. Bounds made with `Bounds::new` or deserialized are never
+/// empty.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "BoundsFields")]
+pub struct Bounds {
+    pub min_x: i64,
+    pub min_y: i64,
+    pub max_x: i64,
+    pub max_y: i64,
+}
+
+// Bounds as they are deserialized, before they are checked
+#[derive(Deserialize)]
+struct BoundsFields {
+    min_x: i64,
+    min_y: i64,
+    max_x: i64,
+    max_y: i64,
+}
+
+impl TryFrom<BoundsFields> for Bounds {
+    type Error = String;
+
+    fn try_from(fields: BoundsFields) -> Result<Bounds, String> {
+        let BoundsFields {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        } = fields;
+        Bounds::new(min_x, min_y, max_x, max_y)
+            .ok_or_else(|| format!("bounds ({min_x}, {min_y}) to ({max_x}, {max_y}) are empty"))
+    }
 }
 
+/// What happens to a cursor that moves across the edge of the world.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
+pub enum EdgePolicy {
+    /// Re-enter from the opposite edge, like on a torus
+    Wrap,
+    /// Turn around and move away from the edge
+    Reflect,
+    Destroy,
+}
+
+/// Which write to a cell takes effect when several cursors write to it in
+/// the same tick.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// The cursor that acts first in the tick
+    FirstWins,
+    /// The cursor that acts last in the tick
+    #[default]
+    LastWins,
+    /// None of them, and the cell keeps its value
+    CancelAll,
+    /// The cursor with the most energy at the start of the tick, or the
+    /// first of them on a tie
+    HighestEnergy,
+}
+
+/// The instruction semantics a cursor follows.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Dialect {
+    /// Befunge-98, except that unknown instructions do nothing, so text can
+    /// sit in the world's path
+    #[default]
+    World,
+    /// Behaves like the Befunge-93 reference interpreter: cells hold signed
+    /// bytes, and division by zero asks the user for the result.
+    Befunge93,
+    /// Befunge-98 as specified, where unknown instructions reflect
+    Befunge98,
+}
+
+/// A part of the world with its own extent and rules, such as a classic
+/// 80x25 Befunge-93 playfield. A cursor inside a region can't leave it, and
+/// `g` and `p` coordinates are relative to the region's corner.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub bounds: Bounds,
+    pub edge_policy: EdgePolicy,
+    pub dialect: Dialect,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WorldConfig {
+    // The extent of the world, or `None` for an infinite world
+    pub bounds: Option<Bounds>,
+    pub edge_policy: EdgePolicy,
+    #[serde(default)]
+    pub dialect: Dialect,
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    // Together with the tick and a cursor's id, decides what `?` does, so
+    // that a world with the same seed always runs the same way
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+}
+
+/// The rules that apply at a position, from the region containing it or
+/// from the world.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rules {
+    pub bounds: Option<Bounds>,
+    pub edge_policy: EdgePolicy,
+    pub dialect: Dialect,
+    // Where `g` and `p` coordinates are measured from
+    pub origin: (i64, i64),
+}
+
+/// How far a cursor moves each tick. The cardinal directions are unit
+/// vectors, but Befunge-98 programs can set any delta with `x`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Delta {
+    pub dx: i64,
+    pub dy: i64,
+}
+
+/// Why a cursor was removed from the grid, so clients can tell a finished
+/// program apart from one that ran out of energy or crashed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum DestroyReason {
+    /// The cursor executed `@`
+    Halted,
+    OutOfEnergy,
+    /// The cursor left the area it is allowed to move in
+    OutOfBounds,
+    Error,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum GridUpdateAction {
     UpdateCell {
-        c: u8,
+        c: i32,
     },
     MoveCursor {
         id: usize,
-        to_x: usize,
-        to_y: usize,
+        to_x: i64,
+        to_y: i64,
     },
     SpawnCursor {
         id: usize,
-        direction: Direction,
+        delta: Delta,
         stack: Vec<i64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stack_stack: Vec<Vec<i64>>,
+        #[serde(default, skip_serializing_if = "is_origin")]
+        storage_offset: (i64, i64),
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        semantics: BTreeMap<char, Vec<i64>>,
         energy: usize,
         string_mode: bool,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        priority: i64,
     },
     DestroyCursor {
         id: usize,
+        reason: DestroyReason,
     },
+    /// Pops and pushes values on one of a cursor's stacks. `stack` counts
+    /// down from the top of the stack of stacks.
     UpdateStack {
         id: usize,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        stack: usize,
         pop: usize,
         push: Vec<i64>,
     },
+    /// Pushes a new stack for `{`, moving `count` values onto it, or pushing
+    /// `-count` zeros onto the old stack if `count` is negative. The old
+    /// storage offset is saved on the old stack.
+    BeginBlock {
+        id: usize,
+        count: i64,
+        storage_offset: (i64, i64),
+    },
+    /// Pops the top stack for `}`, restoring the storage offset and moving
+    /// `count` values to the stack below, or popping `-count` values off it
+    /// if `count` is negative.
+    EndBlock {
+        id: usize,
+        count: i64,
+    },
     ToggleStringMode {
         id: usize,
     },
-    ChangeDirection {
+    ChangeDelta {
         id: usize,
-        direction: Direction,
+        delta: Delta,
     },
     ConsumeEnergy {
         id: usize,
         energy: usize,
     },
+    /// Text printed by a cursor. This does not change the grid and is only
+    /// delivered to subscribers of the cursor's output.
+    Output {
+        id: usize,
+        text: String,
+    },
+    /// A file saved by `o`. This does not change the grid, and is written
+    /// to the simulation's filesystem when the tick is applied.
+    WriteFile {
+        id: usize,
+        name: String,
+        data: Vec<u8>,
+    },
+    /// Text sent to a cursor by a client, queued for `&` and `~`
+    AppendInput {
+        id: usize,
+        text: String,
+    },
+    ConsumeInput {
+        id: usize,
+        count: usize,
+    },
+    /// Binds the letters in `instructions` to a fingerprint, for `(`
+    LoadSemantics {
+        id: usize,
+        fingerprint: i64,
+        instructions: String,
+    },
+    /// Restores the previous meaning of the letters in `instructions`, for `)`
+    UnloadSemantics {
+        id: usize,
+        instructions: String,
+    },
+    /// Replaces the state a fingerprint keeps for a cursor
+    SetFingerprintState {
+        id: usize,
+        fingerprint: i64,
+        state: Vec<i64>,
+    },
+    /// Several cursors wrote to this cell in one tick, and only the writes of
+    /// `winner` were kept, if any. This does not change the grid by itself.
+    WriteConflict {
+        ids: Vec<usize>,
+        winner: Option<usize>,
+    },
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct GridUpdate {
     pub action: GridUpdateAction,
-    pub x: usize,
-    pub y: usize,
+    pub x: i64,
+    pub y: i64,
+}
+
+fn is_zero<T: Default + PartialEq>(v: &T) -> bool {
+    *v == T::default()
+}
+
+fn is_origin(offset: &(i64, i64)) -> bool {
+    *offset == (0, 0)
+}
+
+/// Returns the value `depth` entries below the top of a stack. Popping an
+/// empty stack yields 0, so missing entries read as 0.
+fn peek_stack(stack: &[i64], depth: usize) -> i64 {
+    stack.len().checked_sub(depth + 1).map_or(0, |i| stack[i])
+}
+
+/// Removes the top `count` values of a stack, keeping their order. Missing
+/// values read as 0.
+fn take_top(stack: &mut Vec<i64>, count: i64) -> Vec<i64> {
+    let count = (count.max(0) as usize).min(STACK_TRANSFER_LIMIT);
+    let available = count.min(stack.len());
+    let mut values = vec![0; count - available];
+    values.extend(stack.drain(stack.len() - available..));
+    values
+}
+
+/// Splits an absolute coordinate into the coordinate of its chunk and the
+/// offset within that chunk. Negative coordinates belong to negative chunks.
+fn split_coordinate(v: i64) -> (i64, usize) {
+    let width = CHUNK_WIDTH as i64;
+    (v.div_euclid(width), v.rem_euclid(width) as usize)
+}
+
+/// The inverse of `split_coordinate`.
+fn join_coordinate(chunk: i64, offset: usize) -> i64 {
+    chunk * CHUNK_WIDTH as i64 + offset as i64
+}
+
+impl Cursor {
+    /// Returns the value `depth` entries below the top of the top stack.
+    pub fn peek(&self, depth: usize) -> i64 {
+        peek_stack(&self.stack, depth)
+    }
+
+    /// Reads a 0gnirts string `depth` entries below the top of the stack.
+    /// Returns the string and how many entries it takes up, including the
+    /// terminating 0. The bottom of the stack also ends the string.
+    pub fn peek_string(&self, depth: usize) -> (String, usize) {
+        let mut text = String::new();
+        let mut depth = depth;
+        while depth < self.stack.len() && self.peek(depth) != 0 {
+            text.push(fingerprint::cell_char(self.peek(depth)));
+            depth += 1;
+        }
+        let len = text.chars().count() + 1;
+        (text, len)
+    }
+
+    /// Returns the value `depth` entries below the top of the second stack,
+    /// or `None` if there is only one stack.
+    pub fn peek_second(&self, depth: usize) -> Option<i64> {
+        Some(peek_stack(self.stack_stack.last()?, depth))
+    }
+
+    /// Returns a stack, counting down from the top of the stack of stacks.
+    pub fn stack_mut(&mut self, stack: usize) -> Option<&mut Vec<i64>> {
+        match stack {
+            0 => Some(&mut self.stack),
+            _ => {
+                let index = self.stack_stack.len().checked_sub(stack)?;
+                self.stack_stack.get_mut(index)
+            }
+        }
+    }
+
+    /// Applies an update that only affects this cursor. Other updates are
+    /// ignored.
+    pub fn apply(&mut self, action: GridUpdateAction) {
+        match action {
+            GridUpdateAction::UpdateStack {
+                stack, pop, push, ..
+            } => {
+                let stack = self.stack_mut(stack).unwrap();
+                for _ in 0..pop {
+                    stack.pop();
+                }
+                for value in push {
+                    stack.push(value);
+                }
+            }
+            GridUpdateAction::BeginBlock {
+                count,
+                storage_offset,
+                ..
+            } => {
+                let toss = take_top(&mut self.stack, count);
+                if count < 0 {
+                    let zeros = count.unsigned_abs().min(STACK_TRANSFER_LIMIT as u64);
+                    self.stack.extend((0..zeros).map(|_| 0));
+                }
+                self.stack.push(self.storage_offset.0);
+                self.stack.push(self.storage_offset.1);
+                let soss = std::mem::replace(&mut self.stack, toss);
+                self.stack_stack.push(soss);
+                self.storage_offset = storage_offset;
+            }
+            GridUpdateAction::EndBlock { count, .. } => {
+                let mut soss = self.stack_stack.pop().unwrap();
+                let y = soss.pop().unwrap_or(0);
+                let x = soss.pop().unwrap_or(0);
+                self.storage_offset = (x, y);
+                soss.extend(take_top(&mut self.stack, count));
+                if count < 0 {
+                    let n = count.unsigned_abs().min(soss.len() as u64) as usize;
+                    soss.truncate(soss.len() - n);
+                }
+                self.stack = soss;
+            }
+            GridUpdateAction::ChangeDelta { delta, .. } => {
+                self.delta = delta;
+            }
+            GridUpdateAction::ConsumeEnergy { energy, .. } => {
+                self.energy -= energy;
+            }
+            GridUpdateAction::ToggleStringMode { .. } => {
+                self.string_mode = !self.string_mode;
+            }
+            GridUpdateAction::AppendInput { text, .. } => {
+                let room = INPUT_QUEUE_LIMIT.saturating_sub(self.input.len());
+                self.input.extend(text.chars().take(room));
+            }
+            GridUpdateAction::ConsumeInput { count, .. } => {
+                self.input.drain(..count.min(self.input.len()));
+            }
+            GridUpdateAction::LoadSemantics {
+                fingerprint,
+                instructions,
+                ..
+            } => {
+                for c in instructions.chars() {
+                    self.semantics.entry(c).or_default().push(fingerprint);
+                }
+            }
+            GridUpdateAction::UnloadSemantics { instructions, .. } => {
+                for c in instructions.chars() {
+                    if let Some(stack) = self.semantics.get_mut(&c) {
+                        stack.pop();
+                        if stack.is_empty() {
+                            self.semantics.remove(&c);
+                        }
+                    }
+                }
+            }
+            GridUpdateAction::SetFingerprintState {
+                fingerprint, state, ..
+            } => {
+                if state.is_empty() {
+                    self.fingerprint_state.remove(&fingerprint);
+                } else {
+                    self.fingerprint_state.insert(fingerprint, state);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Delta {
+    pub const UP: Delta = Delta { dx: 0, dy: -1 };
+    pub const DOWN: Delta = Delta { dx: 0, dy: 1 };
+    pub const LEFT: Delta = Delta { dx: -1, dy: 0 };
+    pub const RIGHT: Delta = Delta { dx: 1, dy: 0 };
+
+    pub fn reverse(self) -> Delta {
+        Delta {
+            dx: self.dx.wrapping_neg(),
+            dy: self.dy.wrapping_neg(),
+        }
+    }
+
+    /// Rotates 90 degrees counterclockwise, as seen with y pointing down.
+    pub fn turn_left(self) -> Delta {
+        Delta {
+            dx: self.dy,
+            dy: self.dx.wrapping_neg(),
+        }
+    }
+
+    /// Rotates 90 degrees clockwise, as seen with y pointing down.
+    pub fn turn_right(self) -> Delta {
+        Delta {
+            dx: self.dy.wrapping_neg(),
+            dy: self.dx,
+        }
+    }
+}
+
+impl Bounds {
+    /// Returns `None` if the bounds would be empty or inverted.
+    pub fn new(min_x: i64, min_y: i64, max_x: i64, max_y: i64) -> Option<Bounds> {
+        (min_x < max_x && min_y < max_y).then_some(Bounds {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        })
+    }
+
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        (self.min_x..self.max_x).contains(&x) && (self.min_y..self.max_y).contains(&y)
+    }
+
+    /// Whether any cell of the chunk lies within the bounds.
+    pub fn overlaps_chunk(&self, chunk_x: i64, chunk_y: i64) -> bool {
+        chunk_overlaps(chunk_x, self.min_x, self.max_x)
+            && chunk_overlaps(chunk_y, self.min_y, self.max_y)
+    }
+
+    /// Maps a position back into the bounds as if they were a torus.
+    pub fn wrap(&self, x: i64, y: i64) -> (i64, i64) {
+        (
+            wrap_coordinate(x, self.min_x, self.max_x),
+            wrap_coordinate(y, self.min_y, self.max_y),
+        )
+    }
+}
+
+/// Wraps `value` into `min..max`. The distances between them can be wider
+/// than an `i64`, so this works in `i128`. Empty ranges wrap to `min`.
+fn wrap_coordinate(value: i64, min: i64, max: i64) -> i64 {
+    let width = (max as i128 - min as i128).max(1);
+    (min as i128 + (value as i128 - min as i128).rem_euclid(width)) as i64
+}
+
+/// Whether any cell of `chunk` lies in `min..max`. Chunk coordinates can
+/// come from clients, and the chunks at the far ends of them start or end
+/// past the range of an `i64`, so this works in `i128`.
+fn chunk_overlaps(chunk: i64, min: i64, max: i64) -> bool {
+    let start = chunk as i128 * CHUNK_WIDTH as i128;
+    start < max as i128 && start + CHUNK_WIDTH as i128 > min as i128
+}
+
+impl WorldConfig {
+    pub fn rules_at(&self, x: i64, y: i64) -> Rules {
+        match self.regions.iter().find(|r| r.bounds.contains(x, y)) {
+            Some(region) => Rules {
+                bounds: Some(region.bounds),
+                edge_policy: region.edge_policy,
+                dialect: region.dialect,
+                origin: (region.bounds.min_x, region.bounds.min_y),
+            },
+            None => Rules {
+                bounds: self.bounds,
+                edge_policy: self.edge_policy,
+                dialect: self.dialect,
+                origin: (0, 0),
+            },
+        }
+    }
+
+    pub fn contains_chunk(&self, chunk_x: i64, chunk_y: i64) -> bool {
+        self.bounds
+            .is_none_or(|bounds| bounds.overlaps_chunk(chunk_x, chunk_y))
+    }
+}
+
+impl Rules {
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        self.bounds.is_none_or(|bounds| bounds.contains(x, y))
+    }
+
+    /// Converts `g` and `p` coordinates, which are relative to the storage
+    /// offset, to an absolute position. Returns `None` if the position is
+    /// outside the world or region.
+    pub fn resolve(&self, storage_offset: (i64, i64), x: i64, y: i64) -> Option<(i64, i64)> {
+        let x = x.wrapping_add(self.origin.0).wrapping_add(storage_offset.0);
+        let y = y.wrapping_add(self.origin.1).wrapping_add(storage_offset.1);
+        self.contains(x, y).then_some((x, y))
+    }
+
+    /// Returns the cell one `delta` away from `(x, y)`, wrapping around the
+    /// edges if the edge policy allows it.
+    pub fn advance(&self, x: i64, y: i64, delta: Delta) -> Option<(i64, i64)> {
+        let (x, y) = (x.wrapping_add(delta.dx), y.wrapping_add(delta.dy));
+        match self.bounds {
+            Some(bounds) if !bounds.contains(x, y) => {
+                (self.edge_policy == EdgePolicy::Wrap).then(|| bounds.wrap(x, y))
+            }
+            _ => Some((x, y)),
+        }
+    }
+}
+
+impl Default for WorldConfig {
+    /// A torus of `CHUNK_LIMIT` by `CHUNK_LIMIT` chunks starting at the origin
+    fn default() -> WorldConfig {
+        let width = join_coordinate(CHUNK_LIMIT as i64, 0);
+        WorldConfig {
+            bounds: Bounds::new(0, 0, width, width),
+            edge_policy: EdgePolicy::Wrap,
+            dialect: Dialect::World,
+            regions: vec![],
+            seed: 0,
+            conflict_policy: ConflictPolicy::LastWins,
+        }
+    }
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
         Chunk {
-            cells: [b' '; CHUNK_WIDTH * CHUNK_WIDTH],
+            cells: [b' ' as i32; CHUNK_WIDTH * CHUNK_WIDTH],
             cursors: HashMap::new(),
         }
     }
 
-    pub fn get(&self, x: usize, y: usize) -> u8 {
+    /// Encodes the cells as base64, row by row, each as a little-endian
+    /// 32-bit integer.
+    pub fn encode_cells(&self) -> String {
+        BASE64_STANDARD.encode(self.cells.map(i32::to_le_bytes).concat())
+    }
+
+    /// Decodes cells encoded by `encode_cells`, or returns `None` if `data`
+    /// isn't a chunk's worth of them.
+    pub fn decode_cells(data: &str) -> Option<[i32; CHUNK_WIDTH * CHUNK_WIDTH]> {
+        let bytes = BASE64_STANDARD.decode(data).ok()?;
+        let mut cells = [0; CHUNK_WIDTH * CHUNK_WIDTH];
+        if bytes.len() != cells.len() * 4 {
+            return None;
+        }
+        for (cell, bytes) in cells.iter_mut().zip(bytes.chunks_exact(4)) {
+            *cell = i32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        Some(cells)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> i32 {
         self.cells[y * CHUNK_WIDTH + x]
     }
 
-    pub fn set(&mut self, x: usize, y: usize, c: u8) {
+    pub fn set(&mut self, x: usize, y: usize, c: i32) {
         self.cells[y * CHUNK_WIDTH + x] = c;
     }
 }
@@ -108,6 +700,12 @@ impl Grid {
             ticks: 0,
             chunks: HashMap::new(),
             cursor_chunks: HashMap::new(),
+            config: WorldConfig::default(),
+            next_cursor_id: 0,
+            dirty_chunks: HashSet::new(),
+            paged_out: HashSet::new(),
+            pager: None,
+            paged_in: RefCell::new(HashMap::new()),
         }
     }
 
@@ -120,32 +718,82 @@ impl Grid {
                 x = 0;
                 y += 1;
             } else {
-                grid.set_cell(x, y, c as u8);
+                grid.set_cell(x, y, c as i32);
                 x += 1;
             }
         }
         grid
     }
 
-    pub fn get_cell(&self, x: usize, y: usize) -> u8 {
-        let chunk_x = x / CHUNK_WIDTH;
-        let chunk_y = y / CHUNK_WIDTH;
-        let chunk = self.chunks.get(&(chunk_x, chunk_y));
-        match chunk {
-            Some(chunk) => chunk.get(x % CHUNK_WIDTH, y % CHUNK_WIDTH),
-            None => b' ',
+    /// Creates a world that is a single 80x25 Befunge-93 playfield.
+    pub fn new_befunge93(source: &str) -> Grid {
+        let mut grid = Grid::new();
+        grid.config.bounds = Some(grid.load_befunge93(0, 0, source));
+        grid.config.edge_policy = EdgePolicy::Wrap;
+        grid.config.dialect = Dialect::Befunge93;
+        grid
+    }
+
+    /// Loads a Befunge-93 program into a new 80x25 toroidal region with its
+    /// top left corner at `(x, y)`, and returns the bounds of that region.
+    pub fn add_befunge93_region(&mut self, x: i64, y: i64, source: &str) -> Bounds {
+        let bounds = self.load_befunge93(x, y, source);
+        self.config.regions.push(Region {
+            bounds,
+            edge_policy: EdgePolicy::Wrap,
+            dialect: Dialect::Befunge93,
+        });
+        bounds
+    }
+
+    /// Writes a Befunge-93 program into the 80x25 playfield at `(x, y)`. Like
+    /// the reference interpreter, anything past column 80 or line 25 of the
+    /// source is dropped.
+    fn load_befunge93(&mut self, x: i64, y: i64, source: &str) -> Bounds {
+        for (dy, line) in source.lines().take(25).enumerate() {
+            for (dx, c) in line.bytes().filter(|c| *c != b'\r').take(80).enumerate() {
+                self.set_cell(x + dx as i64, y + dy as i64, c as i32);
+            }
+        }
+        Bounds {
+            min_x: x,
+            min_y: y,
+            max_x: x + 80,
+            max_y: y + 25,
+        }
+    }
+
+    pub fn get_cell(&self, x: i64, y: i64) -> i32 {
+        let (chunk_x, local_x) = split_coordinate(x);
+        let (chunk_y, local_y) = split_coordinate(y);
+        if let Some(chunk) = self.chunks.get(&(chunk_x, chunk_y)) {
+            return chunk.get(local_x, local_y);
         }
+        if !self.paged_out.contains(&(chunk_x, chunk_y)) {
+            return b' ' as i32;
+        }
+        let mut paged_in = self.paged_in.borrow_mut();
+        let chunk = paged_in
+            .entry((chunk_x, chunk_y))
+            .or_insert_with(|| self.load_paged(chunk_x, chunk_y));
+        chunk.get(local_x, local_y)
     }
 
-    pub fn set_cell(&mut self, x: usize, y: usize, c: u8) {
-        let chunk_x = x / CHUNK_WIDTH;
-        let chunk_y = y / CHUNK_WIDTH;
+    pub fn set_cell(&mut self, x: i64, y: i64, c: i32) {
+        let (chunk_x, local_x) = split_coordinate(x);
+        let (chunk_y, local_y) = split_coordinate(y);
+        // Clearing a cell that was never written doesn't need a chunk
+        let exists = self.chunks.contains_key(&(chunk_x, chunk_y))
+            || self.paged_out.contains(&(chunk_x, chunk_y));
+        if !exists && c == b' ' as i32 {
+            return;
+        }
         let chunk = self.get_chunk_mut(chunk_x, chunk_y);
-        chunk.set(x % CHUNK_WIDTH, y % CHUNK_WIDTH, c);
+        chunk.set(local_x, local_y, c);
     }
 
     pub fn get_cursor(&self, id: usize) -> Option<&Cursor> {
-        let (chunk_x, chunk_y) = self.cursor_chunks[&id];
+        let (chunk_x, chunk_y) = *self.cursor_chunks.get(&id)?;
         let chunk = self.chunks.get(&(chunk_x, chunk_y));
         match chunk {
             Some(chunk) => chunk.cursors.get(&id),
@@ -154,7 +802,7 @@ impl Grid {
     }
 
     pub fn get_cursor_mut(&mut self, id: usize) -> Option<&mut Cursor> {
-        let (chunk_x, chunk_y) = self.cursor_chunks[&id];
+        let (chunk_x, chunk_y) = *self.cursor_chunks.get(&id)?;
         let chunk = self.chunks.get_mut(&(chunk_x, chunk_y));
         match chunk {
             Some(chunk) => chunk.cursors.get_mut(&id),
@@ -162,14 +810,85 @@ impl Grid {
         }
     }
 
-    pub fn get_chunk_mut(&mut self, chunk_x: usize, chunk_y: usize) -> &mut Chunk {
+    /// Returns the absolute position of a cursor, if it exists.
+    pub fn get_cursor_position(&self, id: usize) -> Option<(i64, i64)> {
+        let (chunk_x, chunk_y) = *self.cursor_chunks.get(&id)?;
+        let cursor = self.get_cursor(id)?;
+        Some((
+            join_coordinate(chunk_x, cursor.x),
+            join_coordinate(chunk_y, cursor.y),
+        ))
+    }
+
+    /// Reserves an id for a new cursor. Ids are never reused, so clients can
+    /// tell a new cursor apart from one that was destroyed.
+    pub fn allocate_cursor_id(&mut self) -> usize {
+        let id = self.next_cursor_id;
+        self.next_cursor_id += 1;
+        id
+    }
+
+    pub fn get_chunk_mut(&mut self, chunk_x: i64, chunk_y: i64) -> &mut Chunk {
+        self.page_in(chunk_x, chunk_y);
+        self.dirty_chunks.insert((chunk_x, chunk_y));
         // Try finding an existing chunk, or create a new one
         self.chunks
             .entry((chunk_x, chunk_y))
             .or_insert_with(Chunk::new)
     }
 
+    /// Reads a paged out chunk back from the pager. Failing to is fatal,
+    /// since carrying on without the chunk would overwrite it later.
+    fn load_paged(&self, chunk_x: i64, chunk_y: i64) -> Chunk {
+        let pager = self
+            .pager
+            .as_ref()
+            .expect("chunks paged out without a pager");
+        match pager.load_chunk(chunk_x, chunk_y) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => panic!("paged out chunk ({chunk_x}, {chunk_y}) is missing"),
+            Err(e) => panic!("failed to page in chunk ({chunk_x}, {chunk_y}): {e}"),
+        }
+    }
+
+    /// Brings a paged out chunk back into memory, if it is paged out.
+    pub fn page_in(&mut self, chunk_x: i64, chunk_y: i64) {
+        if self.paged_out.remove(&(chunk_x, chunk_y)) {
+            let chunk = match self.paged_in.get_mut().remove(&(chunk_x, chunk_y)) {
+                Some(chunk) => chunk,
+                None => self.load_paged(chunk_x, chunk_y),
+            };
+            self.chunks.insert((chunk_x, chunk_y), chunk);
+        }
+    }
+
+    /// Drops the chunks without cursors from memory, except those `keep`
+    /// accepts, leaving them to the pager. Only chunks that have been saved
+    /// since they last changed are paged out. Returns how many were.
+    pub fn page_out<F: Fn(&(i64, i64)) -> bool>(&mut self, keep: F) -> usize {
+        if self.pager.is_none() {
+            return 0;
+        }
+        self.paged_in.get_mut().clear();
+        let cold = self
+            .chunks
+            .iter()
+            .filter(|(pos, chunk)| {
+                chunk.cursors.is_empty() && !self.dirty_chunks.contains(pos) && !keep(pos)
+            })
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        for pos in &cold {
+            self.chunks.remove(pos);
+            self.paged_out.insert(*pos);
+        }
+        cold.len()
+    }
+
     pub fn apply(&mut self, update: GridUpdate) {
+        update.visit_chunks(|chunk_x, chunk_y| {
+            self.dirty_chunks.insert((chunk_x, chunk_y));
+        });
         let x = update.x;
         let y = update.y;
         match update.action {
@@ -179,13 +898,13 @@ impl Grid {
             GridUpdateAction::MoveCursor { id, to_x, to_y } => {
                 let (cur_chunk_x, cur_chunk_y) = self.cursor_chunks[&id];
                 let cur_chunk = self.chunks.get_mut(&(cur_chunk_x, cur_chunk_y)).unwrap();
-                let new_chunk_x = to_x / CHUNK_WIDTH;
-                let new_chunk_y = to_y / CHUNK_WIDTH;
+                let (new_chunk_x, local_x) = split_coordinate(to_x);
+                let (new_chunk_y, local_y) = split_coordinate(to_y);
                 if cur_chunk_x != new_chunk_x || cur_chunk_y != new_chunk_y {
                     // Move cursor to a new chunk
                     let mut cursor = cur_chunk.cursors.remove(&id).unwrap();
-                    cursor.x = to_x % CHUNK_WIDTH;
-                    cursor.y = to_y % CHUNK_WIDTH;
+                    cursor.x = local_x;
+                    cursor.y = local_y;
                     let new_chunk = self.get_chunk_mut(new_chunk_x, new_chunk_y);
                     new_chunk.cursors.insert(id, cursor);
 
@@ -194,58 +913,65 @@ impl Grid {
                 } else {
                     // Move cursor within the same chunk
                     let cursor = cur_chunk.cursors.get_mut(&id).unwrap();
-                    cursor.x = to_x % CHUNK_WIDTH;
-                    cursor.y = to_y % CHUNK_WIDTH;
+                    cursor.x = local_x;
+                    cursor.y = local_y;
                 }
             }
             GridUpdateAction::SpawnCursor {
                 id,
-                direction,
+                delta,
                 stack,
+                stack_stack,
+                storage_offset,
+                semantics,
                 energy,
                 string_mode,
+                priority,
             } => {
-                let chunk_x = x / CHUNK_WIDTH;
-                let chunk_y = y / CHUNK_WIDTH;
+                let (chunk_x, local_x) = split_coordinate(x);
+                let (chunk_y, local_y) = split_coordinate(y);
                 let chunk = self.get_chunk_mut(chunk_x, chunk_y);
                 chunk.cursors.insert(
                     id,
                     Cursor {
-                        x: x % CHUNK_WIDTH,
-                        y: y % CHUNK_WIDTH,
-                        direction,
+                        x: local_x,
+                        y: local_y,
+                        delta,
                         stack,
                         energy,
                         string_mode,
+                        stack_stack,
+                        storage_offset,
+                        semantics,
+                        fingerprint_state: BTreeMap::new(),
+                        input: VecDeque::new(),
+                        priority,
                     },
                 );
                 self.cursor_chunks.insert(id, (chunk_x, chunk_y));
+                self.next_cursor_id = self.next_cursor_id.max(id + 1);
             }
-            GridUpdateAction::DestroyCursor { id } => {
+            GridUpdateAction::DestroyCursor { id, .. } => {
                 let (chunk_x, chunk_y) = self.cursor_chunks.remove(&id).unwrap();
                 let chunk = self.chunks.get_mut(&(chunk_x, chunk_y)).unwrap();
                 chunk.cursors.remove(&id);
             }
-            GridUpdateAction::UpdateStack { id, pop, push } => {
+            GridUpdateAction::Output { .. }
+            | GridUpdateAction::WriteFile { .. }
+            | GridUpdateAction::WriteConflict { .. } => {}
+            GridUpdateAction::UpdateStack { id, .. }
+            | GridUpdateAction::BeginBlock { id, .. }
+            | GridUpdateAction::EndBlock { id, .. }
+            | GridUpdateAction::ChangeDelta { id, .. }
+            | GridUpdateAction::ConsumeEnergy { id, .. }
+            | GridUpdateAction::ToggleStringMode { id }
+            | GridUpdateAction::AppendInput { id, .. }
+            | GridUpdateAction::ConsumeInput { id, .. }
+            | GridUpdateAction::LoadSemantics { id, .. }
+            | GridUpdateAction::UnloadSemantics { id, .. }
+            | GridUpdateAction::SetFingerprintState { id, .. } => {
                 let cursor = self.get_cursor_mut(id).unwrap();
-                for _ in 0..pop {
-                    cursor.stack.pop();
-                }
-                for value in push {
-                    cursor.stack.push(value);
-                }
-            }
-            GridUpdateAction::ChangeDirection { id, direction } => {
-                let cursor = self.get_cursor_mut(id).unwrap();
-                cursor.direction = direction;
-            }
-            GridUpdateAction::ConsumeEnergy { id, energy } => {
-                let cursor = self.get_cursor_mut(id).unwrap();
-                cursor.energy -= energy;
-            }
-            GridUpdateAction::ToggleStringMode { id } => {
-                let cursor = self.get_cursor_mut(id).unwrap();
-                cursor.string_mode = !cursor.string_mode;
+                cursor.apply(update.action);
             }
         }
     }
@@ -253,13 +979,13 @@ impl Grid {
 
 impl Display for Grid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.chunks.len() == 0 {
+        if self.chunks.is_empty() {
             return Ok(());
         }
-        let mut min_chunk_x = usize::MAX;
-        let mut max_chunk_x = usize::MIN;
-        let mut min_chunk_y = usize::MAX;
-        let mut max_chunk_y = usize::MIN;
+        let mut min_chunk_x = i64::MAX;
+        let mut max_chunk_x = i64::MIN;
+        let mut min_chunk_y = i64::MAX;
+        let mut max_chunk_y = i64::MIN;
         for (chunk_x, chunk_y) in self.chunks.keys() {
             min_chunk_x = min_chunk_x.min(*chunk_x);
             max_chunk_x = max_chunk_x.max(*chunk_x);
@@ -267,20 +993,17 @@ impl Display for Grid {
             max_chunk_y = max_chunk_y.max(*chunk_y);
         }
 
-        let mut min_x = usize::MAX;
-        let mut max_x = usize::MIN;
-        let mut min_y = usize::MAX;
-        let mut max_y = usize::MIN;
-        for y in min_chunk_y * CHUNK_WIDTH..max_chunk_y * CHUNK_WIDTH + CHUNK_WIDTH {
-            for x in min_chunk_x * CHUNK_WIDTH..max_chunk_x * CHUNK_WIDTH + CHUNK_WIDTH {
-                match self.get_cell(x, y) {
-                    b' ' => {}
-                    _ => {
-                        min_x = min_x.min(x);
-                        max_x = max_x.max(x);
-                        min_y = min_y.min(y);
-                        max_y = max_y.max(y);
-                    }
+        let mut min_x = i64::MAX;
+        let mut max_x = i64::MIN;
+        let mut min_y = i64::MAX;
+        let mut max_y = i64::MIN;
+        for y in join_coordinate(min_chunk_y, 0)..join_coordinate(max_chunk_y + 1, 0) {
+            for x in join_coordinate(min_chunk_x, 0)..join_coordinate(max_chunk_x + 1, 0) {
+                if self.get_cell(x, y) != b' ' as i32 {
+                    min_x = min_x.min(x);
+                    max_x = max_x.max(x);
+                    min_y = min_y.min(y);
+                    max_y = max_y.max(y);
                 }
             }
         }
@@ -291,23 +1014,23 @@ impl Display for Grid {
             .flat_map(|((chunk_x, chunk_y), chunk)| {
                 chunk.cursors.values().map(move |cursor| {
                     (
-                        (chunk_x * CHUNK_WIDTH) + cursor.x,
-                        (chunk_y * CHUNK_WIDTH) + cursor.y,
+                        join_coordinate(*chunk_x, cursor.x),
+                        join_coordinate(*chunk_y, cursor.y),
                     )
                 })
             })
-            .collect::<HashSet<(usize, usize)>>();
+            .collect::<HashSet<(i64, i64)>>();
 
         let mut lines = vec![];
         for y in min_y..=max_y {
             let mut line = String::new();
             for x in min_x..=max_x {
                 if cursor_positions.contains(&(x, y)) {
-                    line.extend("\x1b[7m".chars());
-                    line.push(self.get_cell(x, y) as char);
-                    line.extend("\x1b[0m".chars());
+                    line.push_str("\x1b[7m");
+                    line.push(cell_char(self.get_cell(x, y) as i64));
+                    line.push_str("\x1b[0m");
                 } else {
-                    line.push(self.get_cell(x, y) as char);
+                    line.push(cell_char(self.get_cell(x, y) as i64));
                 }
             }
             lines.push(line);
@@ -320,19 +1043,89 @@ impl Display for Grid {
 }
 
 impl GridUpdate {
-    pub fn visit_chunks<F: FnMut(usize, usize)>(&self, mut cond: F) {
-        cond(self.x / CHUNK_WIDTH, self.y / CHUNK_WIDTH);
-        match self.action {
-            GridUpdateAction::MoveCursor { to_x, to_y, .. } => {
-                let chunk_x = self.x / CHUNK_WIDTH;
-                let chunk_y = self.y / CHUNK_WIDTH;
-                let chunk_x2 = to_x / CHUNK_WIDTH;
-                let chunk_y2 = to_y / CHUNK_WIDTH;
-                if chunk_x != chunk_x2 || chunk_y != chunk_y2 {
-                    cond(chunk_x2, chunk_y2);
-                }
+    pub fn visit_chunks<F: FnMut(i64, i64)>(&self, mut cond: F) {
+        let (chunk_x, _) = split_coordinate(self.x);
+        let (chunk_y, _) = split_coordinate(self.y);
+        cond(chunk_x, chunk_y);
+        if let GridUpdateAction::MoveCursor { to_x, to_y, .. } = self.action {
+            let (chunk_x2, _) = split_coordinate(to_x);
+            let (chunk_y2, _) = split_coordinate(to_y);
+            if chunk_x != chunk_x2 || chunk_y != chunk_y2 {
+                cond(chunk_x2, chunk_y2);
             }
-            _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::{collect_output, run, send_input, simulate};
+
+    #[test]
+    fn cells_hold_wide_values() {
+        let mut simulation = simulate("\"→λ\",,@", 0);
+        assert_eq!(simulation.grid.get_cell(2, 0), 'λ' as i32);
+        assert_eq!(collect_output(&mut simulation, 10), "λ→");
+
+        assert_eq!(run("ff*ff**00p00g", 13), vec![50625]);
+        assert_eq!(run("0ff*ff**-00p00g", 15), vec![-50625]);
+        // Wide cells aren't instructions, so the world passes over them
+        assert_eq!(run("1λ2", 3), vec![1, 2]);
+        assert_eq!(run("'λ", 1), vec!['λ' as i64]);
+    }
+
+    #[test]
+    fn wrap_handles_distances_wider_than_i64() {
+        let bounds = Bounds::new(-100, -100, 100, 100).unwrap();
+        assert_eq!(bounds.wrap(i64::MAX, 0), (7, 0));
+        assert_eq!(bounds.wrap(0, i64::MIN), (0, -8));
+        let far = Bounds::new(i64::MIN, 0, i64::MAX, 1).unwrap();
+        assert_eq!(far.wrap(i64::MAX, 5), (i64::MIN, 0));
+    }
+
+    #[test]
+    fn empty_bounds_are_rejected() {
+        assert_eq!(Bounds::new(0, 0, 0, 5), None);
+        assert_eq!(Bounds::new(0, 5, 10, 0), None);
+        let empty = r#"{"min_x":3,"min_y":0,"max_x":3,"max_y":5}"#;
+        assert!(serde_json::from_str::<Bounds>(empty).is_err());
+        let bounds = r#"{"min_x":0,"min_y":0,"max_x":3,"max_y":5}"#;
+        assert_eq!(
+            serde_json::from_str::<Bounds>(bounds).unwrap(),
+            Bounds::new(0, 0, 3, 5).unwrap()
+        );
+
+        // Empty bounds built by hand still don't panic
+        let empty = Bounds {
+            min_x: 3,
+            min_y: 0,
+            max_x: 3,
+            max_y: 5,
+        };
+        assert_eq!(empty.wrap(10, 7), (3, 2));
+    }
+
+    #[test]
+    fn far_chunks_are_outside_the_bounds() {
+        let bounds = Bounds::new(0, 0, 100, 100).unwrap();
+        assert!(bounds.overlaps_chunk(3, 0));
+        assert!(!bounds.overlaps_chunk(4, 0));
+        assert!(!bounds.overlaps_chunk(i64::MAX, 0));
+        assert!(!bounds.overlaps_chunk(0, i64::MIN));
+        let far = Bounds::new(i64::MIN, i64::MIN, i64::MAX, i64::MAX).unwrap();
+        assert!(far.overlaps_chunk(i64::MAX / 32, i64::MIN / 32));
+        assert!(!far.overlaps_chunk(i64::MAX, 0));
+        assert!(!far.overlaps_chunk(0, i64::MIN));
+    }
+
+    #[test]
+    fn input_queue_is_capped() {
+        let mut simulation = simulate("~", 0);
+        send_input(&mut simulation, &"a".repeat(INPUT_QUEUE_LIMIT - 1));
+        send_input(&mut simulation, "bc");
+        let input = &simulation.grid.get_cursor(0).unwrap().input;
+        assert_eq!(input.len(), INPUT_QUEUE_LIMIT);
+        assert_eq!(input.back(), Some(&'b'));
+    }
+}
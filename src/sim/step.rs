@@ -1,118 +1,162 @@
-use crate::sim::{Direction, Grid, GridUpdate, GridUpdateAction, CHUNK_WIDTH};
+use crate::sim::fingerprint::FingerprintRegistry;
+use crate::sim::instructions::{Instruction, InstructionSets, Outcome};
+use crate::sim::vfs::Vfs;
+use crate::sim::{
+    join_coordinate, ConflictPolicy, DestroyReason, EdgePolicy, Grid, GridUpdate, GridUpdateAction,
+};
 use rand::prelude::SmallRng;
-use rand::{Rng, SeedableRng};
+use rand::SeedableRng;
+use std::cmp::Reverse;
+use std::collections::HashMap;
 
-struct SimulationStep<'g> {
-    updates: Vec<GridUpdate>,
-    rng: &'g mut SmallRng,
-    grid: &'g Grid,
+/// Mixes `value` into `hash` with the SplitMix64 finalizer.
+fn mix(hash: u64, value: u64) -> u64 {
+    let mut z = (hash ^ value).wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// The random numbers a cursor draws during a tick. Each cursor gets its own
+/// stream, so the order cursors are stepped in doesn't change the results.
+fn cursor_rng(seed: u64, id: usize, tick: usize) -> SmallRng {
+    SmallRng::seed_from_u64(mix(mix(seed, id as u64), tick as u64))
+}
+
+/// One tick of a simulation. Instructions read the grid as it was at the
+/// start of the tick and record their effects as updates.
+pub struct SimulationStep<'g> {
+    pub(crate) updates: Vec<GridUpdate>,
+    // The stream of the cursor being stepped
+    pub(crate) rng: SmallRng,
+    pub(crate) grid: &'g Grid,
+    // Ids handed to cursors spawned during this step start here
+    pub(crate) next_cursor_id: usize,
+    pub(crate) fingerprints: &'g FingerprintRegistry,
+    pub(crate) vfs: Option<&'g Vfs>,
+    instruction_sets: &'g InstructionSets,
+    // The cursor that made each update
+    sources: Vec<usize>,
 }
 
 impl SimulationStep<'_> {
-    pub fn step_cursor(&mut self, id: usize, chunk_pos: (usize, usize)) {
-        let chunk = self.grid.chunks.get(&chunk_pos).unwrap();
+    /// Executes an instruction with the instruction set of the dialect at
+    /// the cursor, and returns how the cursor moves afterwards. In string
+    /// mode, every cell but `"` is pushed instead, whatever the dialect.
+    pub fn execute(&mut self, instruction: &Instruction) -> Outcome {
+        let Instruction {
+            id,
+            x,
+            y,
+            cursor,
+            rules,
+            c,
+        } = *instruction;
+        if cursor.string_mode {
+            let action = if c == b'"' as i32 {
+                GridUpdateAction::ToggleStringMode { id }
+            } else {
+                GridUpdateAction::UpdateStack {
+                    id,
+                    stack: 0,
+                    pop: 0,
+                    push: vec![c as i64],
+                }
+            };
+            self.updates.push(GridUpdate { x, y, action });
+            return Outcome::Move {
+                delta: cursor.delta,
+                distance: 1,
+            };
+        }
+
+        let outcome = match self.instruction_sets.get(rules.dialect) {
+            Some(set) => set.execute(self, instruction),
+            None => Outcome::Unknown,
+        };
+        match outcome {
+            // Instructions a set leaves undefined do nothing
+            Outcome::Unknown => Outcome::Move {
+                delta: cursor.delta,
+                distance: 1,
+            },
+            outcome => outcome,
+        }
+    }
+
+    pub fn step_cursor(&mut self, id: usize, chunk_pos: (i64, i64)) {
+        let grid = self.grid;
+        let chunk = grid.chunks.get(&chunk_pos).unwrap();
         let cursor = chunk.cursors.get(&id).unwrap();
 
-        let mut direction = cursor.direction;
-        let abs_x = cursor.x + chunk_pos.0 * CHUNK_WIDTH;
-        let abs_y = cursor.y + chunk_pos.1 * CHUNK_WIDTH;
+        let abs_x = join_coordinate(chunk_pos.0, cursor.x);
+        let abs_y = join_coordinate(chunk_pos.1, cursor.y);
+        let rules = grid.config.rules_at(abs_x, abs_y);
+        let c = chunk.get(cursor.x, cursor.y);
+        self.rng = cursor_rng(grid.config.seed, id, grid.ticks);
+        let instruction = Instruction {
+            id,
+            x: abs_x,
+            y: abs_y,
+            cursor,
+            rules: &rules,
+            c,
+        };
+        let Outcome::Move { delta, distance } = self.execute(&instruction) else {
+            return;
+        };
 
-        if cursor.string_mode {
-            match chunk.get(cursor.x, cursor.y) {
-                b'"' => self.updates.push(GridUpdate {
-                    x: abs_x,
-                    y: abs_y,
-                    action: GridUpdateAction::ToggleStringMode { id },
-                }),
-                c => {
+        if cursor.energy == 0 {
+            self.updates.push(GridUpdate {
+                x: abs_x,
+                y: abs_y,
+                action: GridUpdateAction::DestroyCursor {
+                    id,
+                    reason: DestroyReason::OutOfEnergy,
+                },
+            });
+            return;
+        }
+
+        let dx = delta.dx.wrapping_mul(distance);
+        let dy = delta.dy.wrapping_mul(distance);
+        let mut to_x = abs_x.wrapping_add(dx);
+        let mut to_y = abs_y.wrapping_add(dy);
+        if let Some(bounds) = rules.bounds.filter(|b| !b.contains(to_x, to_y)) {
+            match rules.edge_policy {
+                EdgePolicy::Wrap => (to_x, to_y) = bounds.wrap(to_x, to_y),
+                EdgePolicy::Reflect => {
                     self.updates.push(GridUpdate {
                         x: abs_x,
                         y: abs_y,
-                        action: GridUpdateAction::UpdateStack {
+                        action: GridUpdateAction::ChangeDelta {
                             id,
-                            pop: 0,
-                            push: vec![c as i64],
+                            delta: delta.reverse(),
                         },
                     });
+                    (to_x, to_y) = (abs_x.wrapping_sub(dx), abs_y.wrapping_sub(dy));
+                    if !bounds.contains(to_x, to_y) {
+                        (to_x, to_y) = (abs_x, abs_y);
+                    }
                 }
-            }
-        } else {
-            match chunk.get(cursor.x, cursor.y) {
-                b'^' => {
-                    direction = Direction::Up;
-                    self.updates.push(GridUpdate {
-                        x: abs_x,
-                        y: abs_y,
-                        action: GridUpdateAction::ChangeDirection { id, direction },
-                    });
-                }
-                b'v' => {
-                    direction = Direction::Down;
-                    self.updates.push(GridUpdate {
-                        x: abs_x,
-                        y: abs_y,
-                        action: GridUpdateAction::ChangeDirection { id, direction },
-                    });
-                }
-                b'<' => {
-                    direction = Direction::Left;
+                EdgePolicy::Destroy => {
                     self.updates.push(GridUpdate {
                         x: abs_x,
                         y: abs_y,
-                        action: GridUpdateAction::ChangeDirection { id, direction },
-                    });
-                }
-                b'>' => {
-                    direction = Direction::Right;
-                    self.updates.push(GridUpdate {
-                        x: abs_x,
-                        y: abs_y,
-                        action: GridUpdateAction::ChangeDirection { id, direction },
-                    });
-                }
-                b'?' => {
-                    direction = match self.rng.gen_range(0..4) {
-                        0 => Direction::Up,
-                        1 => Direction::Down,
-                        2 => Direction::Left,
-                        3 => Direction::Right,
-                        _ => unreachable!(),
-                    };
-                    self.updates.push(GridUpdate {
-                        x: abs_x,
-                        y: abs_y,
-                        action: GridUpdateAction::ChangeDirection { id, direction },
+                        action: GridUpdateAction::DestroyCursor {
+                            id,
+                            reason: DestroyReason::OutOfBounds,
+                        },
                     });
+                    return;
                 }
-                _ => {}
             }
         }
 
-        if cursor.energy == 0 {
-            self.updates.push(GridUpdate {
-                x: abs_x,
-                y: abs_y,
-                action: GridUpdateAction::DestroyCursor { id },
-            });
-            return;
-        }
-
         self.updates.push(GridUpdate {
             x: abs_x,
             y: abs_y,
-            action: GridUpdateAction::MoveCursor {
-                id,
-                to_x: match direction {
-                    Direction::Left => abs_x - 1,
-                    Direction::Right => abs_x + 1,
-                    _ => abs_x,
-                },
-                to_y: match direction {
-                    Direction::Up => abs_y - 1,
-                    Direction::Down => abs_y + 1,
-                    _ => abs_y,
-                },
-            },
+            action: GridUpdateAction::MoveCursor { id, to_x, to_y },
         });
 
         self.updates.push(GridUpdate {
@@ -122,39 +166,766 @@ impl SimulationStep<'_> {
         });
     }
 
+    /// Steps every cursor, those with the highest priority first and then by
+    /// id. When two cursors write to the same cell in one tick, the write
+    /// that is kept is decided by `resolve_conflicts` according to the
+    /// world's `ConflictPolicy`. This order only breaks ties there, by
+    /// deciding which cursor wrote first or last.
     pub fn step_grid(&mut self) {
-        for (id, chunk_pos) in self.grid.cursor_chunks.iter() {
-            self.step_cursor(*id, *chunk_pos);
+        let grid = self.grid;
+        let mut order = grid
+            .cursor_chunks
+            .iter()
+            .map(|(id, chunk_pos)| {
+                let priority = grid.chunks[chunk_pos].cursors[id].priority;
+                (Reverse(priority), *id, *chunk_pos)
+            })
+            .collect::<Vec<_>>();
+        order.sort_unstable();
+        for (_, id, chunk_pos) in order {
+            self.step_cursor(id, chunk_pos);
+            self.sources.resize(self.updates.len(), id);
+        }
+    }
+}
+
+/// Settles the cells that more than one cursor wrote to in a tick, by the
+/// world's conflict policy. `sources` holds the cursor that made each update.
+/// The writes of the cursors that lose are dropped, and each conflict is
+/// reported with a `WriteConflict` update after the rest.
+fn resolve_conflicts(grid: &Grid, updates: Vec<GridUpdate>, sources: &[usize]) -> Vec<GridUpdate> {
+    // The cursors writing to each cell, in the order they acted
+    let mut writers = HashMap::<(i64, i64), Vec<usize>>::new();
+    let mut cells = vec![];
+    for (update, id) in updates.iter().zip(sources) {
+        if let GridUpdateAction::UpdateCell { .. } = update.action {
+            let ids = writers.entry((update.x, update.y)).or_insert_with(|| {
+                cells.push((update.x, update.y));
+                vec![]
+            });
+            if !ids.contains(id) {
+                ids.push(*id);
+            }
         }
     }
+
+    let mut conflicts = vec![];
+    let mut winners = HashMap::new();
+    for (x, y) in cells {
+        let ids = writers.remove(&(x, y)).unwrap();
+        if ids.len() < 2 {
+            continue;
+        }
+        let winner = match grid.config.conflict_policy {
+            ConflictPolicy::FirstWins => ids.first().copied(),
+            ConflictPolicy::LastWins => ids.last().copied(),
+            ConflictPolicy::CancelAll => None,
+            // Searching backwards makes the first cursor win a tie
+            ConflictPolicy::HighestEnergy => ids
+                .iter()
+                .rev()
+                .copied()
+                .max_by_key(|id| grid.get_cursor(*id).map_or(0, |cursor| cursor.energy)),
+        };
+        winners.insert((x, y), winner);
+        conflicts.push(GridUpdate {
+            x,
+            y,
+            action: GridUpdateAction::WriteConflict { ids, winner },
+        });
+    }
+
+    let mut resolved = updates
+        .into_iter()
+        .zip(sources)
+        .filter(|(update, id)| match update.action {
+            GridUpdateAction::UpdateCell { .. } => winners
+                .get(&(update.x, update.y))
+                .is_none_or(|winner| *winner == Some(**id)),
+            _ => true,
+        })
+        .map(|(update, _)| update)
+        .collect::<Vec<_>>();
+    resolved.extend(conflicts);
+    resolved
 }
 
 pub struct Simulation {
-    rng: SmallRng,
     pub grid: Grid,
+    // Fingerprints cursors can load with `(`
+    pub fingerprints: FingerprintRegistry,
+    // Files for `i` and `o`, which reflect without one
+    pub vfs: Option<Vfs>,
+    // The instructions of each dialect
+    pub instruction_sets: InstructionSets,
 }
 
 impl Simulation {
     pub fn new(grid: Grid) -> Simulation {
         Simulation {
-            rng: SmallRng::from_entropy(),
             grid,
+            fingerprints: FingerprintRegistry::standard(),
+            vfs: None,
+            instruction_sets: InstructionSets::standard(),
         }
     }
 
     pub fn step(&mut self) -> Vec<GridUpdate> {
         let mut step = SimulationStep {
             updates: Vec::new(),
-            rng: &mut self.rng,
+            // Replaced for each cursor
+            rng: SmallRng::seed_from_u64(0),
             grid: &self.grid,
+            next_cursor_id: self.grid.next_cursor_id,
+            fingerprints: &self.fingerprints,
+            vfs: self.vfs.as_ref(),
+            instruction_sets: &self.instruction_sets,
+            sources: Vec::new(),
         };
         step.step_grid();
-        let updates = step.updates;
+        let updates = resolve_conflicts(&self.grid, step.updates, &step.sources);
         self.grid.ticks += 1;
         for update in updates.iter() {
             println!("{:?}", update);
+            if let GridUpdateAction::WriteFile { name, data, .. } = &update.action {
+                // `o` checked the quota, so this only fails if the files
+                // changed since
+                if let Some(Err(e)) = self.vfs.as_ref().map(|vfs| vfs.write(name, data)) {
+                    eprintln!("Failed to write file {:?}: {}", name, e);
+                }
+            }
             self.grid.apply(update.clone());
         }
         updates
     }
+
+    /// Copies the whole world, with the chunks that are paged out read back
+    /// into memory. The tick count and seed come with it, and they are all
+    /// the random state there is, so a restored world picks up exactly
+    /// where the snapshot left off.
+    pub fn snapshot(&self) -> Grid {
+        let mut snapshot = self.grid.clone();
+        for &(chunk_x, chunk_y) in &self.grid.paged_out {
+            snapshot.page_in(chunk_x, chunk_y);
+        }
+        snapshot.pager = None;
+        snapshot.dirty_chunks.clear();
+        snapshot
+    }
+
+    /// Replaces the world with a snapshot, keeping the current pager. Every
+    /// chunk of either world is left dirty, so the next save writes the
+    /// snapshot's chunks and removes the rest. Cursor ids count up from
+    /// where they were in the snapshot, so cursors spawned after this get
+    /// the ids, and random streams, they got the first time. Subscriptions
+    /// to cursors the snapshot doesn't have are left for the caller to drop.
+    pub fn restore(&mut self, snapshot: Grid) {
+        let old = std::mem::replace(&mut self.grid, snapshot);
+        self.grid.dirty_chunks = old
+            .chunks
+            .keys()
+            .chain(&old.paged_out)
+            .chain(self.grid.chunks.keys())
+            .copied()
+            .collect();
+        self.grid.pager = old.pager;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::testing::{
+        collect_output, delta_after, run, send_input, simulate, simulate_grid, simulate_with_config,
+    };
+    use crate::sim::{Bounds, ConflictPolicy, Delta, WorldConfig, STACK_TRANSFER_LIMIT};
+    use std::collections::{BTreeMap, VecDeque};
+
+    #[test]
+    fn digits_push_their_value() {
+        assert_eq!(run("0129", 4), vec![0, 1, 2, 9]);
+    }
+
+    #[test]
+    fn duplicate_swap_and_discard() {
+        assert_eq!(run("12:", 3), vec![1, 2, 2]);
+        assert_eq!(run("12\\", 3), vec![2, 1]);
+        assert_eq!(run("12$", 3), vec![1]);
+    }
+
+    #[test]
+    fn empty_stack_reads_as_zero() {
+        assert_eq!(run(":", 1), vec![0, 0]);
+        assert_eq!(run("1\\", 2), vec![1, 0]);
+        assert!(run("$", 1).is_empty());
+    }
+
+    #[test]
+    fn logical_not_and_greater_than() {
+        assert_eq!(run("0!5!", 4), vec![1, 0]);
+        assert_eq!(run("32`23`", 6), vec![1, 0]);
+    }
+
+    #[test]
+    fn horizontal_and_vertical_branches() {
+        // `_` sends zero right and nonzero left, where the 1 is pushed again
+        assert_eq!(run("0_7", 3), vec![7]);
+        assert_eq!(run("5 1_", 5), vec![5, 1]);
+        // `|` sends zero down and nonzero up
+        assert_eq!(run("0|\n 8", 3), vec![8]);
+        assert_eq!(run("v\n  4\n>1|", 6), vec![4]);
+    }
+
+    #[test]
+    fn get_and_put() {
+        assert_eq!(run("10g", 3), vec![b'0' as i64]);
+        assert_eq!(run("99g", 3), vec![b' ' as i64]);
+        assert_eq!(run("01-0g", 5), vec![b' ' as i64]);
+        let simulation = simulate("77*50p", 6);
+        assert_eq!(simulation.grid.get_cell(5, 0), b'1' as i32);
+        assert!(simulation.grid.get_cursor(0).unwrap().stack.is_empty());
+    }
+
+    #[test]
+    fn put_reaches_target_chunk() {
+        let mut simulation = simulate("99*9+:99*p", 9);
+        let updates = simulation.step();
+        let cell_update = updates
+            .iter()
+            .find(|u| matches!(u.action, GridUpdateAction::UpdateCell { .. }))
+            .unwrap();
+        let mut chunks = vec![];
+        cell_update.visit_chunks(|x, y| chunks.push((x, y)));
+        assert_eq!(chunks, vec![(2, 2)]);
+        assert_eq!(simulation.grid.get_cell(90, 81), b'Z' as i32);
+    }
+
+    #[test]
+    fn trampoline_skips_next_cell() {
+        assert_eq!(run("#12", 2), vec![2]);
+    }
+
+    #[test]
+    fn halt_destroys_cursor() {
+        let mut simulation = simulate("1@2", 1);
+        let updates = simulation.step();
+        assert_eq!(
+            updates,
+            vec![GridUpdate {
+                x: 1,
+                y: 0,
+                action: GridUpdateAction::DestroyCursor {
+                    id: 0,
+                    reason: DestroyReason::Halted,
+                },
+            }]
+        );
+        assert!(simulation.grid.cursor_chunks.is_empty());
+    }
+
+    #[test]
+    fn output_number_and_character() {
+        let mut simulation = simulate("52*:..\"!\",", 4);
+        let mut output = String::new();
+        for _ in 0..6 {
+            for update in simulation.step() {
+                if let GridUpdateAction::Output { id: 0, text } = update.action {
+                    output.push_str(&text);
+                }
+            }
+        }
+        assert_eq!(output, "10 10 !");
+    }
+
+    #[test]
+    fn input_blocks_until_available() {
+        let mut simulation = simulate("~&&", 0);
+        assert!(simulation.step().is_empty());
+        assert!(simulation.step().is_empty());
+        simulation.grid.apply(GridUpdate {
+            x: 0,
+            y: 0,
+            action: GridUpdateAction::AppendInput {
+                id: 0,
+                text: "a x-12 3".to_string(),
+            },
+        });
+        simulation.step();
+        simulation.step();
+        // The 3 may be followed by more digits, so the second `&` waits
+        assert!(simulation.step().is_empty());
+        simulation.grid.apply(GridUpdate {
+            x: 0,
+            y: 0,
+            action: GridUpdateAction::AppendInput {
+                id: 0,
+                text: "4\n".to_string(),
+            },
+        });
+        simulation.step();
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert_eq!(cursor.stack, vec![b'a' as i64, -12, 34]);
+        assert_eq!(cursor.input, VecDeque::from(['\n']));
+        assert_eq!(cursor.energy, 997);
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let infinite = WorldConfig {
+            bounds: None,
+            ..WorldConfig::default()
+        };
+        // Heading left from the origin moves into the chunk at (-1, 0)
+        let mut simulation = simulate_with_config("<", infinite.clone(), 1);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((-1, 0)));
+        assert_eq!(simulation.grid.cursor_chunks[&0], (-1, 0));
+        simulation.grid.set_cell(-2, 0, b'^' as i32);
+        simulation.step();
+        simulation.step();
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((-2, -1)));
+
+        let simulation = simulate_with_config("701-0p", infinite, 6);
+        assert_eq!(simulation.grid.get_cell(-1, 0), b'\x07' as i32);
+    }
+
+    fn bounded(edge_policy: EdgePolicy) -> WorldConfig {
+        WorldConfig {
+            bounds: Some(Bounds {
+                min_x: 0,
+                min_y: 0,
+                max_x: 4,
+                max_y: 2,
+            }),
+            edge_policy,
+            ..WorldConfig::default()
+        }
+    }
+
+    #[test]
+    fn edge_policies() {
+        // Wrapping re-enters from the opposite edge
+        let simulation = simulate_with_config("1234", bounded(EdgePolicy::Wrap), 5);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((1, 0)));
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().stack,
+            vec![1, 2, 3, 4, 1]
+        );
+        let simulation = simulate_with_config("<", bounded(EdgePolicy::Wrap), 1);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((3, 0)));
+
+        // Reflecting turns around at the edge
+        let simulation = simulate_with_config("1234", bounded(EdgePolicy::Reflect), 5);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((1, 0)));
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().delta, Delta::LEFT);
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().stack,
+            vec![1, 2, 3, 4, 3]
+        );
+
+        // Destroying removes the cursor instead of moving it off the edge
+        let mut simulation = simulate_with_config("1234", bounded(EdgePolicy::Destroy), 3);
+        let updates = simulation.step();
+        assert_eq!(
+            updates.last().unwrap().action,
+            GridUpdateAction::DestroyCursor {
+                id: 0,
+                reason: DestroyReason::OutOfBounds
+            }
+        );
+        assert!(simulation.grid.cursor_chunks.is_empty());
+    }
+
+    #[test]
+    fn get_and_put_outside_bounds() {
+        let simulation = simulate("01-0p01-0g", 10);
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().stack,
+            vec![b' ' as i64]
+        );
+        assert!(!simulation.grid.chunks.contains_key(&(-1, 0)));
+    }
+
+    #[test]
+    fn befunge93_hello_world() {
+        let grid = Grid::new_befunge93("\"!dlroW ,olleH\">:#,_@");
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        assert_eq!(collect_output(&mut simulation, 1000), "Hello, World!");
+        assert!(simulation.grid.cursor_chunks.is_empty());
+    }
+
+    #[test]
+    fn befunge93_wraps_around_playfield() {
+        let source = format!("<{}@.7\n", " ".repeat(76));
+        let grid = Grid::new_befunge93(&source);
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        assert_eq!(collect_output(&mut simulation, 10), "7 ");
+
+        let grid = Grid::new_befunge93("^\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n@\n.\n3");
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        assert_eq!(collect_output(&mut simulation, 10), "3 ");
+    }
+
+    #[test]
+    fn befunge93_division_by_zero_asks_for_result() {
+        let grid = Grid::new_befunge93("50/.50%.@");
+        let mut simulation = simulate_grid(grid, (0, 0), 3);
+        assert!(simulation.step().is_empty());
+        send_input(&mut simulation, " 42\n-3\n");
+        assert_eq!(collect_output(&mut simulation, 10), "42 -3 ");
+    }
+
+    #[test]
+    fn befunge93_cells_are_signed_bytes() {
+        let grid = Grid::new_befunge93("55*8*00p00g.@");
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        assert_eq!(collect_output(&mut simulation, 20), "-56 ");
+    }
+
+    #[test]
+    fn befunge93_region_uses_local_coordinates() {
+        let mut grid = Grid::new();
+        grid.add_befunge93_region(100, 50, "10g.@");
+        let mut simulation = simulate_grid(grid, (100, 50), 0);
+        assert_eq!(collect_output(&mut simulation, 10), "48 ");
+    }
+
+    #[test]
+    fn befunge93_reads_numbers_like_scanf() {
+        let grid = Grid::new_befunge93("&.&.~.@");
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        send_input(&mut simulation, "  -17x");
+        assert_eq!(collect_output(&mut simulation, 10), "-17 0 120 ");
+    }
+
+    #[test]
+    fn befunge98_delta_instructions() {
+        let simulation = simulate("21x", 3);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((4, 1)));
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().delta,
+            Delta { dx: 2, dy: 1 }
+        );
+
+        assert_eq!(delta_after("[", 1), Delta::UP);
+        assert_eq!(delta_after("]", 1), Delta::DOWN);
+        assert_eq!(delta_after("x[", 2), Delta { dx: 0, dy: 0 });
+        assert_eq!(run("1r", 3), vec![1, 1]);
+
+        assert_eq!(delta_after("12w", 3), Delta::UP);
+        assert_eq!(delta_after("21w", 3), Delta::DOWN);
+        assert_eq!(delta_after("11w", 3), Delta::RIGHT);
+    }
+
+    #[test]
+    fn befunge98_jump() {
+        assert_eq!(run("2j123", 3), vec![3]);
+        assert_eq!(run("0j1", 3), vec![1]);
+    }
+
+    #[test]
+    fn befunge93_ignores_befunge98_instructions() {
+        let grid = Grid::new_befunge93("1r.@");
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        assert_eq!(collect_output(&mut simulation, 10), "1 ");
+    }
+
+    #[test]
+    fn random_directions_follow_the_seed() {
+        // Positions of three cursors wandering over a field of `?`
+        let walk = |seed: u64| {
+            let mut config = bounded(EdgePolicy::Wrap);
+            config.seed = seed;
+            let mut grid = Grid::new_from_string("????\n????");
+            grid.config = config;
+            for (id, x) in [(1, 1), (2, 2)] {
+                grid.apply(GridUpdate {
+                    x,
+                    y: 1,
+                    action: GridUpdateAction::SpawnCursor {
+                        id,
+                        delta: Delta::RIGHT,
+                        stack: vec![],
+                        stack_stack: vec![],
+                        storage_offset: (0, 0),
+                        semantics: BTreeMap::new(),
+                        energy: 1000,
+                        string_mode: false,
+                        priority: 0,
+                    },
+                });
+            }
+            let mut simulation = simulate_grid(grid, (0, 0), 0);
+            (0..30)
+                .map(|_| {
+                    simulation.step();
+                    (0..3)
+                        .map(|id| simulation.grid.get_cursor_position(id))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(walk(1), walk(1));
+        assert_ne!(walk(1), walk(2));
+    }
+
+    /// Sets up cursor 0 writing `a` and cursor 1 writing `b` to the cell at
+    /// (9, 9) in their fourth tick, and runs the first three.
+    fn race(priority: i64, energy: usize, conflict_policy: ConflictPolicy) -> Simulation {
+        let mut grid = Grid::new_from_string("'a99p@\n'b99p@");
+        grid.config.conflict_policy = conflict_policy;
+        grid.apply(GridUpdate {
+            x: 0,
+            y: 1,
+            action: GridUpdateAction::SpawnCursor {
+                id: 1,
+                delta: Delta::RIGHT,
+                stack: vec![],
+                stack_stack: vec![],
+                storage_offset: (0, 0),
+                semantics: BTreeMap::new(),
+                energy,
+                string_mode: false,
+                priority,
+            },
+        });
+        simulate_grid(grid, (0, 0), 3)
+    }
+
+    #[test]
+    fn later_cursors_win_write_conflicts() {
+        let winner = |priority: i64| {
+            let mut simulation = race(priority, 1000, ConflictPolicy::LastWins);
+            simulation.step();
+            simulation.grid.get_cell(9, 9)
+        };
+        // By default cursors act in order of id
+        assert_eq!(winner(0), b'b' as i32);
+        // A cursor with a higher priority acts before the others
+        assert_eq!(winner(1), b'a' as i32);
+        assert_eq!(winner(-1), b'b' as i32);
+    }
+
+    #[test]
+    fn write_conflict_policies() {
+        let resolve = |energy: usize, policy: ConflictPolicy| {
+            let mut simulation = race(0, energy, policy);
+            let updates = simulation.step();
+            let conflicts = updates
+                .iter()
+                .filter(|update| matches!(update.action, GridUpdateAction::WriteConflict { .. }))
+                .collect::<Vec<_>>();
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!((conflicts[0].x, conflicts[0].y), (9, 9));
+            let GridUpdateAction::WriteConflict { ids, winner } = &conflicts[0].action else {
+                unreachable!();
+            };
+            assert_eq!(ids, &vec![0, 1]);
+            (*winner, simulation.grid.get_cell(9, 9))
+        };
+        assert_eq!(
+            resolve(1000, ConflictPolicy::FirstWins),
+            (Some(0), b'a' as i32)
+        );
+        assert_eq!(
+            resolve(1000, ConflictPolicy::LastWins),
+            (Some(1), b'b' as i32)
+        );
+        assert_eq!(
+            resolve(1000, ConflictPolicy::CancelAll),
+            (None, b' ' as i32)
+        );
+        // Cursor 0 has spent 3 energy, so cursor 1 wins with 998 left
+        assert_eq!(
+            resolve(1001, ConflictPolicy::HighestEnergy),
+            (Some(1), b'b' as i32)
+        );
+        assert_eq!(
+            resolve(997, ConflictPolicy::HighestEnergy),
+            (Some(0), b'a' as i32)
+        );
+        // On a tie, the cursor that acted first wins
+        assert_eq!(
+            resolve(1000, ConflictPolicy::HighestEnergy),
+            (Some(0), b'a' as i32)
+        );
+
+        // A cursor writing to a cell twice is not a conflict
+        let mut simulation = simulate("'a99'b992kp@", 7);
+        assert!(simulation
+            .step()
+            .iter()
+            .all(|update| !matches!(update.action, GridUpdateAction::WriteConflict { .. })));
+        assert_eq!(simulation.grid.get_cell(9, 9), b'a' as i32);
+    }
+
+    #[test]
+    fn split_spawns_child_behind_parent() {
+        let mut simulation = simulate_with_config("5t.@", bounded(EdgePolicy::Wrap), 2);
+        let child = simulation.grid.get_cursor(1).unwrap();
+        assert_eq!(child.stack, vec![5]);
+        assert_eq!(child.delta, Delta::LEFT);
+        assert_eq!(child.energy, 499);
+        assert_eq!(simulation.grid.get_cursor_position(1), Some((0, 0)));
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().energy, 499);
+        assert_eq!(simulation.grid.next_cursor_id, 2);
+
+        // The child wraps around onto the `@` while the parent prints
+        assert_eq!(collect_output(&mut simulation, 10), "5 ");
+        assert!(simulation.grid.cursor_chunks.is_empty());
+    }
+
+    #[test]
+    fn split_needs_room_behind_parent() {
+        let simulation = simulate_with_config("t", bounded(EdgePolicy::Destroy), 1);
+        assert_eq!(simulation.grid.cursor_chunks.len(), 1);
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().energy, 999);
+    }
+
+    #[test]
+    fn begin_and_end_block() {
+        let simulation = simulate("1232{", 5);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert_eq!(cursor.stack, vec![2, 3]);
+        assert_eq!(cursor.stack_stack, vec![vec![1, 0, 0]]);
+        assert_eq!(cursor.storage_offset, (5, 0));
+
+        let simulation = simulate("1232{1}", 7);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert_eq!(cursor.stack, vec![1, 3]);
+        assert!(cursor.stack_stack.is_empty());
+        assert_eq!(cursor.storage_offset, (0, 0));
+
+        // Negative counts push zeros onto the old stack, and pop values off
+        // it again when the block ends
+        let simulation = simulate("101-{", 5);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert!(cursor.stack.is_empty());
+        assert_eq!(cursor.stack_stack, vec![vec![1, 0, 0, 0]]);
+        assert_eq!(run("101-{01-}", 9), vec![1]);
+    }
+
+    #[test]
+    fn storage_offset_moves_get() {
+        assert_eq!(run("1232{00g", 8), vec![2, 3, b'0' as i64]);
+    }
+
+    #[test]
+    fn transfer_between_stacks() {
+        let simulation = simulate("120{4502-u", 10);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert!(cursor.stack.is_empty());
+        assert_eq!(cursor.stack_stack, vec![vec![1, 2, 0, 0, 5, 4]]);
+
+        assert_eq!(run("120{4502-u2u", 12), vec![4, 5]);
+    }
+
+    #[test]
+    fn stack_transfers_are_capped() {
+        // 4096 values are asked for each time, but only 1024 move
+        let simulation = simulate("88*:*{", 6);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert_eq!(cursor.stack.len(), STACK_TRANSFER_LIMIT);
+
+        let simulation = simulate("088*:*-{", 8);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert_eq!(cursor.stack_stack[0].len(), STACK_TRANSFER_LIMIT + 2);
+
+        assert_eq!(run("{88*:*u", 7).len(), STACK_TRANSFER_LIMIT);
+        assert_eq!(run("{88*:*}", 7).len(), STACK_TRANSFER_LIMIT);
+    }
+
+    #[test]
+    fn end_block_without_second_stack_reflects() {
+        assert_eq!(delta_after("}", 1), Delta::LEFT);
+        assert_eq!(delta_after("u", 1), Delta::LEFT);
+    }
+
+    #[test]
+    fn default_stack_fields_are_not_serialized() {
+        let simulation = simulate("1", 1);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        let json = serde_json::to_value(cursor).unwrap();
+        assert!(json.get("stack_stack").is_none());
+        assert!(json.get("storage_offset").is_none());
+
+        let update = GridUpdateAction::UpdateStack {
+            id: 0,
+            stack: 0,
+            pop: 1,
+            push: vec![],
+        };
+        let json = serde_json::to_value(update).unwrap();
+        assert!(json["UpdateStack"].get("stack").is_none());
+    }
+
+    #[test]
+    fn hex_digits_push_their_value() {
+        assert_eq!(run("abcdef", 6), vec![10, 11, 12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn fetch_and_store_character() {
+        assert_eq!(run("'A1", 2), vec![b'A' as i64, 1]);
+        // The stored cell is skipped, not executed
+        let simulation = simulate("'Xs 1", 2);
+        assert_eq!(simulation.grid.get_cell(3, 0), b'X' as i32);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((4, 0)));
+        assert!(simulation.grid.get_cursor(0).unwrap().stack.is_empty());
+    }
+
+    #[test]
+    fn jump_over_skips_to_matching_semicolon() {
+        assert_eq!(run(";123;4", 2), vec![4]);
+        assert_eq!(delta_after(";", 1), Delta::LEFT);
+    }
+
+    #[test]
+    fn iterate_repeats_next_instruction() {
+        let simulation = simulate("3k1", 2);
+        let cursor = simulation.grid.get_cursor(0).unwrap();
+        assert_eq!(cursor.stack, vec![1, 1, 1]);
+        assert_eq!(simulation.grid.get_cursor_position(0), Some((3, 0)));
+        // One energy for each tick and one for each repetition
+        assert_eq!(cursor.energy, 1000 - 2 - 3);
+
+        assert_eq!(run("2k ;x; 5", 2), vec![5, 5]);
+        assert_eq!(run("0k12", 3), vec![2]);
+        assert_eq!(run("14k:", 3), vec![1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn iterate_turning_moves_from_k() {
+        assert_eq!(delta_after("1k[", 2), Delta::UP);
+        assert_eq!(delta_after("2k[", 2), Delta::LEFT);
+    }
+
+    #[test]
+    fn iterate_stops_when_out_of_energy() {
+        let simulation = simulate("a:*:*k1", 6);
+        assert!(simulation.grid.cursor_chunks.is_empty());
+    }
+
+    #[test]
+    fn iterate_waits_for_all_input() {
+        let mut simulation = simulate("2k~", 2);
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().stack, vec![2]);
+        send_input(&mut simulation, "a");
+        simulation.step();
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().stack, vec![2]);
+        send_input(&mut simulation, "b");
+        simulation.step();
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().stack,
+            vec![b'a' as i64, b'b' as i64]
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("73-", 3), vec![4]);
+        assert_eq!(run("73/", 3), vec![2]);
+        assert_eq!(run("73%", 3), vec![1]);
+        assert_eq!(run("23*4+", 5), vec![10]);
+        assert_eq!(run("70/", 3), vec![0]);
+    }
 }
@@ -0,0 +1,356 @@
+use crate::sim::{Chunk, Cursor, Grid, WorldConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Key of the tick count, cursor ids and config
+const WORLD_KEY: &[u8] = b"world";
+/// Prefix of chunk keys, which are followed by the chunk's coordinates
+const CHUNK_PREFIX: &[u8] = b"chunk";
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// Something in the tree couldn't be decoded
+    Corrupt(String),
+    Storage(sled::Error),
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Corrupt(what) => write!(f, "corrupt {}", what),
+            StoreError::Storage(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<sled::Error> for StoreError {
+    fn from(e: sled::Error) -> StoreError {
+        StoreError::Storage(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredWorld {
+    ticks: usize,
+    next_cursor_id: usize,
+    config: WorldConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredChunk {
+    cells: String,
+    cursors: HashMap<usize, Cursor>,
+}
+
+impl StoredWorld {
+    fn new(grid: &Grid) -> StoredWorld {
+        StoredWorld {
+            ticks: grid.ticks,
+            next_cursor_id: grid.next_cursor_id,
+            config: grid.config.clone(),
+        }
+    }
+
+    fn into_grid(self) -> Grid {
+        let mut grid = Grid::new();
+        grid.ticks = self.ticks;
+        grid.next_cursor_id = self.next_cursor_id;
+        grid.config = self.config;
+        grid
+    }
+}
+
+impl StoredChunk {
+    fn new(chunk: &Chunk) -> StoredChunk {
+        StoredChunk {
+            cells: chunk.encode_cells(),
+            cursors: chunk.cursors.clone(),
+        }
+    }
+
+    fn into_chunk(self) -> Option<Chunk> {
+        Some(Chunk {
+            cells: Chunk::decode_cells(&self.cells)?,
+            cursors: self.cursors,
+        })
+    }
+}
+
+/// A whole world, kept in a single entry
+#[derive(Serialize, Deserialize)]
+struct StoredSnapshot {
+    world: StoredWorld,
+    chunks: Vec<(i64, i64, StoredChunk)>,
+}
+
+fn chunk_key(chunk_x: i64, chunk_y: i64) -> Vec<u8> {
+    [CHUNK_PREFIX, &chunk_x.to_be_bytes(), &chunk_y.to_be_bytes()].concat()
+}
+
+/// Keeps a world in a sled tree so that it survives restarts. Only the
+/// chunks that changed are written each time the world is saved. Grids
+/// also page chunks they don't need in memory out to their store.
+#[derive(Clone, Debug)]
+pub struct WorldStore {
+    tree: sled::Tree,
+    // Named snapshots of the world, taken with `Simulation::snapshot`
+    snapshots: sled::Tree,
+}
+
+impl WorldStore {
+    pub fn open(db: &sled::Db) -> Result<WorldStore, StoreError> {
+        Ok(WorldStore {
+            tree: db.open_tree("world")?,
+            snapshots: db.open_tree("snapshots")?,
+        })
+    }
+
+    /// Loads the saved world, or returns `None` if there isn't one. Only the
+    /// chunks with cursors are loaded into memory, and the rest are paged
+    /// in when they are needed.
+    pub fn load(&self) -> Result<Option<Grid>, StoreError> {
+        let Some(world) = self.tree.get(WORLD_KEY)? else {
+            return Ok(None);
+        };
+        let world: StoredWorld =
+            serde_json::from_slice(&world).map_err(|_| StoreError::Corrupt("world".to_string()))?;
+        let mut grid = world.into_grid();
+        grid.pager = Some(self.clone());
+        for entry in self.tree.scan_prefix(CHUNK_PREFIX) {
+            let (key, value) = entry?;
+            let (chunk_x, chunk_y, chunk) = decode_chunk(&key, &value)
+                .ok_or_else(|| StoreError::Corrupt(format!("chunk {:?}", key)))?;
+            if chunk.cursors.is_empty() {
+                grid.paged_out.insert((chunk_x, chunk_y));
+                continue;
+            }
+            for id in chunk.cursors.keys() {
+                grid.cursor_chunks.insert(*id, (chunk_x, chunk_y));
+            }
+            grid.chunks.insert((chunk_x, chunk_y), chunk);
+        }
+        Ok(Some(grid))
+    }
+
+    pub fn load_chunk(&self, chunk_x: i64, chunk_y: i64) -> Result<Option<Chunk>, StoreError> {
+        let key = chunk_key(chunk_x, chunk_y);
+        let Some(value) = self.tree.get(&key)? else {
+            return Ok(None);
+        };
+        let (_, _, chunk) = decode_chunk(&key, &value)
+            .ok_or_else(|| StoreError::Corrupt(format!("chunk {:?}", key)))?;
+        Ok(Some(chunk))
+    }
+
+    /// Writes the chunks changed since the last save, along with the rest of
+    /// the grid, in one batch. Dirty chunks the grid no longer has are
+    /// removed. If that fails, the chunks stay dirty for the next save.
+    pub fn save(&self, grid: &mut Grid) -> Result<(), StoreError> {
+        let mut batch = sled::Batch::default();
+        for &(chunk_x, chunk_y) in &grid.dirty_chunks {
+            let key = chunk_key(chunk_x, chunk_y);
+            match grid.chunks.get(&(chunk_x, chunk_y)) {
+                Some(chunk) => {
+                    batch.insert(key, serde_json::to_vec(&StoredChunk::new(chunk)).unwrap())
+                }
+                None if grid.paged_out.contains(&(chunk_x, chunk_y)) => {}
+                None => batch.remove(key),
+            }
+        }
+        let world = StoredWorld::new(grid);
+        batch.insert(WORLD_KEY, serde_json::to_vec(&world).unwrap());
+        self.tree.apply_batch(batch)?;
+        grid.dirty_chunks.clear();
+        Ok(())
+    }
+
+    /// Saves a snapshot under `name`, replacing any snapshot with that name.
+    /// Chunks the snapshot has paged out are left out of it, so it should
+    /// come from `Simulation::snapshot`, which has none.
+    pub fn save_snapshot(&self, name: &str, snapshot: &Grid) -> Result<(), StoreError> {
+        let stored = StoredSnapshot {
+            world: StoredWorld::new(snapshot),
+            chunks: snapshot
+                .chunks
+                .iter()
+                .map(|(&(chunk_x, chunk_y), chunk)| (chunk_x, chunk_y, StoredChunk::new(chunk)))
+                .collect(),
+        };
+        self.snapshots
+            .insert(name, serde_json::to_vec(&stored).unwrap())?;
+        Ok(())
+    }
+
+    /// Loads the snapshot called `name`, or returns `None` if there isn't
+    /// one. The snapshot is entirely in memory and has no pager.
+    pub fn load_snapshot(&self, name: &str) -> Result<Option<Grid>, StoreError> {
+        let Some(value) = self.snapshots.get(name)? else {
+            return Ok(None);
+        };
+        let corrupt = || StoreError::Corrupt(format!("snapshot {:?}", name));
+        let stored: StoredSnapshot = serde_json::from_slice(&value).map_err(|_| corrupt())?;
+        let mut grid = stored.world.into_grid();
+        for (chunk_x, chunk_y, chunk) in stored.chunks {
+            let chunk = chunk.into_chunk().ok_or_else(corrupt)?;
+            for id in chunk.cursors.keys() {
+                grid.cursor_chunks.insert(*id, (chunk_x, chunk_y));
+            }
+            grid.chunks.insert((chunk_x, chunk_y), chunk);
+        }
+        Ok(Some(grid))
+    }
+
+    /// The names of the saved snapshots, in order.
+    pub fn snapshot_names(&self) -> Result<Vec<String>, StoreError> {
+        self.snapshots
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key?;
+                String::from_utf8(key.to_vec())
+                    .map_err(|_| StoreError::Corrupt(format!("snapshot name {:?}", key)))
+            })
+            .collect()
+    }
+}
+
+fn decode_chunk(key: &[u8], value: &[u8]) -> Option<(i64, i64, Chunk)> {
+    let coordinates = key.strip_prefix(CHUNK_PREFIX)?;
+    let chunk_x = i64::from_be_bytes(coordinates.get(..8)?.try_into().ok()?);
+    let chunk_y = i64::from_be_bytes(coordinates.get(8..)?.try_into().ok()?);
+    let stored: StoredChunk = serde_json::from_slice(value).ok()?;
+    Some((chunk_x, chunk_y, stored.into_chunk()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::step::Simulation;
+    use crate::sim::testing::{collect_output, simulate, simulate_grid, temporary_db};
+
+    fn temporary_store() -> WorldStore {
+        WorldStore::open(&temporary_db()).unwrap()
+    }
+
+    /// Sets up `grid` to page out to `store`, with a cursor at the origin.
+    fn simulate_paged(mut grid: Grid, store: &WorldStore) -> Simulation {
+        grid.pager = Some(store.clone());
+        simulate_grid(grid, (0, 0), 0)
+    }
+
+    #[test]
+    fn world_store_saves_changed_chunks() {
+        let store = temporary_store();
+        assert!(store.load().unwrap().is_none());
+
+        let mut simulation = simulate("'λ9f*0p", 3);
+        simulation.grid.config.seed = 42;
+        store.save(&mut simulation.grid).unwrap();
+        assert!(simulation.grid.dirty_chunks.is_empty());
+        assert_eq!(store.load().unwrap(), Some(simulation.grid.clone()));
+
+        // The cursor writes to another chunk, and moves on into the next one
+        for _ in 0..40 {
+            simulation.step();
+        }
+        // The cursor's chunk, the one it moved to, and the one it wrote to
+        assert_eq!(simulation.grid.dirty_chunks.len(), 3);
+        store.save(&mut simulation.grid).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        // Only the chunk with the cursor is loaded, and the rest are paged out
+        assert_eq!(loaded.chunks[&(1, 0)], simulation.grid.chunks[&(1, 0)]);
+        assert_eq!(loaded.chunks.len(), 1);
+        assert!(loaded.paged_out.contains(&(0, 0)) && loaded.paged_out.contains(&(4, 0)));
+        assert_eq!(loaded.get_cell(135, 0), 'λ' as i32);
+        assert_eq!(loaded.get_cursor_position(0), Some((44, 0)));
+    }
+
+    #[test]
+    fn chunks_page_out_and_back_in() {
+        let store = temporary_store();
+        let mut grid = Grid::new_from_string("f9*0g,'Xf9*1p@");
+        grid.set_cell(135, 0, b'Z' as i32);
+        let mut simulation = simulate_paged(grid, &store);
+
+        // Only chunks that are saved and have no cursors are paged out
+        assert_eq!(simulation.grid.page_out(|_| false), 0);
+        store.save(&mut simulation.grid).unwrap();
+        assert_eq!(simulation.grid.page_out(|chunk| *chunk == (0, 0)), 1);
+        assert!(!simulation.grid.chunks.contains_key(&(4, 0)));
+        assert_eq!(simulation.grid.get_cell(135, 0), b'Z' as i32);
+
+        // `g` reads the chunk where it is, and `p` brings it back
+        assert_eq!(collect_output(&mut simulation, 20), "Z");
+        assert!(simulation.grid.chunks.contains_key(&(4, 0)));
+        assert_eq!(simulation.grid.get_cell(135, 1), b'X' as i32);
+
+        // Loading leaves chunks without cursors in the store
+        store.save(&mut simulation.grid).unwrap();
+        let loaded = store.load().unwrap().unwrap();
+        assert!(loaded.chunks.is_empty());
+        assert_eq!(loaded.paged_out.len(), 2);
+        assert_eq!(loaded.get_cell(135, 1), b'X' as i32);
+    }
+
+    #[test]
+    fn snapshots_restore_the_whole_world() {
+        let store = temporary_store();
+        let mut grid = Grid::new_from_string("'Zf9*0p?");
+        grid.set_cell(70, 0, b'Y' as i32);
+        let mut simulation = simulate_paged(grid, &store);
+        store.save(&mut simulation.grid).unwrap();
+        assert_eq!(simulation.grid.page_out(|_| false), 1);
+
+        // Paged out chunks are part of the snapshot
+        store
+            .save_snapshot("start", &simulation.snapshot())
+            .unwrap();
+        for _ in 0..10 {
+            simulation.step();
+        }
+        let after = simulation.snapshot();
+        assert_eq!(after.get_cell(135, 0), b'Z' as i32);
+        store.save(&mut simulation.grid).unwrap();
+
+        let snapshot = store.load_snapshot("start").unwrap().unwrap();
+        assert_eq!(snapshot.get_cell(70, 0), b'Y' as i32);
+        simulation.restore(snapshot);
+        store.save(&mut simulation.grid).unwrap();
+        // The chunk written after the snapshot is removed from the store
+        assert_eq!(store.load_chunk(4, 0).unwrap(), None);
+        assert_eq!(store.load().unwrap().unwrap().get_cell(135, 0), b' ' as i32);
+
+        // The world, random directions and all, runs the same way again
+        for _ in 0..10 {
+            simulation.step();
+        }
+        assert_eq!(simulation.snapshot(), after);
+        assert_eq!(store.snapshot_names().unwrap(), vec!["start"]);
+        assert!(store.load_snapshot("end").unwrap().is_none());
+    }
+
+    #[test]
+    fn restored_worlds_replay_splits() {
+        // The child heads left from the `t`, and wraps onto the other `?`
+        let mut grid = Grid::new_from_string("t?");
+        grid.set_cell(319, 0, b'?' as i32);
+        let mut simulation = simulate_grid(grid, (0, 0), 0);
+        let start = simulation.snapshot();
+        for _ in 0..20 {
+            simulation.step();
+        }
+        let after = simulation.snapshot();
+        assert!(after.cursor_chunks.contains_key(&1));
+
+        // Cursors split off again get the same ids, and so the same random
+        // directions
+        simulation.restore(start);
+        for _ in 0..20 {
+            simulation.step();
+        }
+        assert_eq!(simulation.snapshot(), after);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::sim::GridUpdate;
+use crate::sim::{GridUpdate, GridUpdateAction};
 use slab::Slab;
 use std::collections::{BTreeMap, HashMap, HashSet};
 
@@ -6,9 +6,13 @@ pub trait Subscriber: Send {
     fn notify(&self, updates: Vec<GridUpdate>);
 }
 
+// The chunks a subscriber is subscribed to, and the cursors whose output it follows
+type SubscriberEntry<S> = (HashSet<(i64, i64)>, HashSet<usize>, S);
+
 pub struct SubscriptionManager<S: Subscriber> {
-    pub subscribers: Slab<(HashSet<(usize, usize)>, S)>,
-    pub chunks: HashMap<(usize, usize), HashSet<usize>>,
+    pub subscribers: Slab<SubscriberEntry<S>>,
+    pub chunks: HashMap<(i64, i64), HashSet<usize>>,
+    pub cursors: HashMap<usize, HashSet<usize>>,
 }
 
 impl<S: Subscriber> SubscriptionManager<S> {
@@ -16,70 +20,110 @@ impl<S: Subscriber> SubscriptionManager<S> {
         SubscriptionManager {
             subscribers: Slab::new(),
             chunks: HashMap::new(),
+            cursors: HashMap::new(),
         }
     }
 
     pub fn subscribe(&mut self, subscriber: S) -> usize {
-        let id = self.subscribers.insert((HashSet::new(), subscriber));
-        id
+        self.subscribers
+            .insert((HashSet::new(), HashSet::new(), subscriber))
     }
 
     pub fn unsubscribe(&mut self, id: usize) {
-        for chunk in self.subscribers.remove(id).0 {
-            self.chunks.get_mut(&chunk).unwrap().remove(&id);
-            if self.chunks[&chunk].len() == 0 {
-                self.chunks.remove(&chunk);
-            }
-        }
+        self.remove_subscriber(id);
     }
 
     pub fn notify(&self, updates: Vec<GridUpdate>) {
         let mut update_queue: BTreeMap<usize, Vec<GridUpdate>> = Default::default();
         for update in updates {
+            // Output goes to the subscribers of the cursor, not of its chunk
+            if let GridUpdateAction::Output { id, .. } = update.action {
+                if let Some(subscribers) = self.cursors.get(&id) {
+                    for subscriber in subscribers {
+                        update_queue
+                            .entry(*subscriber)
+                            .or_default()
+                            .push(update.clone());
+                    }
+                }
+                continue;
+            }
+            // Files go to the filesystem, not to subscribers
+            if let GridUpdateAction::WriteFile { .. } = update.action {
+                continue;
+            }
             update.visit_chunks(|chunk_x, chunk_y| {
                 if let Some(subscribers) = self.chunks.get(&(chunk_x, chunk_y)) {
                     for subscriber in subscribers {
                         update_queue
                             .entry(*subscriber)
-                            .or_insert_with(Vec::new)
+                            .or_default()
                             .push(update.clone());
                     }
                 }
             });
         }
         for (id, updates) in update_queue {
-            self.subscribers[id].1.notify(updates);
+            self.subscribers[id].2.notify(updates);
         }
     }
 
-    pub fn subscribe_chunks(&mut self, id: usize, chunks: Vec<(usize, usize)>) {
+    pub fn subscribe_chunks(&mut self, id: usize, chunks: Vec<(i64, i64)>) {
         self.subscribers[id].0.extend(chunks.iter());
         for chunk in chunks {
-            self.chunks
-                .entry(chunk)
-                .or_insert_with(HashSet::new)
-                .insert(id);
+            self.chunks.entry(chunk).or_default().insert(id);
         }
     }
 
-    pub fn unsubscribe_chunk(&mut self, id: usize, chunk_x: usize, chunk_y: usize) {
+    pub fn unsubscribe_chunk(&mut self, id: usize, chunk_x: i64, chunk_y: i64) {
         self.subscribers[id].0.remove(&(chunk_x, chunk_y));
         self.chunks
             .get_mut(&(chunk_x, chunk_y))
             .unwrap()
             .remove(&id);
-        if self.chunks[&(chunk_x, chunk_y)].len() == 0 {
+        if self.chunks[&(chunk_x, chunk_y)].is_empty() {
             self.chunks.remove(&(chunk_x, chunk_y));
         }
     }
 
+    pub fn subscribe_cursor(&mut self, id: usize, cursor: usize) {
+        self.subscribers[id].1.insert(cursor);
+        self.cursors.entry(cursor).or_default().insert(id);
+    }
+
+    pub fn unsubscribe_cursor(&mut self, id: usize, cursor: usize) {
+        self.subscribers[id].1.remove(&cursor);
+        if let Some(subscribers) = self.cursors.get_mut(&cursor) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                self.cursors.remove(&cursor);
+            }
+        }
+    }
+
+    /// Drops every subscription to the output of `cursor`, and returns the
+    /// subscribers that had one.
+    pub fn remove_cursor(&mut self, cursor: usize) -> HashSet<usize> {
+        let subscribers = self.cursors.remove(&cursor).unwrap_or_default();
+        for id in &subscribers {
+            self.subscribers[*id].1.remove(&cursor);
+        }
+        subscribers
+    }
+
     pub fn remove_subscriber(&mut self, id: usize) {
-        let (chunks, _) = self.subscribers.remove(id);
+        let (chunks, cursors, _) = self.subscribers.remove(id);
         for chunk in chunks {
             self.chunks.get_mut(&chunk).unwrap().remove(&id);
-            if self.chunks[&chunk].len() == 0 {
+            if self.chunks[&chunk].is_empty() {
                 self.chunks.remove(&chunk);
             }
         }
+        for cursor in cursors {
+            self.cursors.get_mut(&cursor).unwrap().remove(&id);
+            if self.cursors[&cursor].is_empty() {
+                self.cursors.remove(&cursor);
+            }
+        }
     }
 }
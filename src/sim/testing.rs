@@ -0,0 +1,97 @@
+use crate::sim::step::Simulation;
+use crate::sim::{Delta, Grid, GridUpdate, GridUpdateAction, WorldConfig};
+use std::collections::BTreeMap;
+
+/// Runs `source` for `steps` ticks with a single cursor starting at the
+/// origin heading right.
+pub(crate) fn simulate(source: &str, steps: usize) -> Simulation {
+    simulate_with_config(source, WorldConfig::default(), steps)
+}
+
+pub(crate) fn simulate_with_config(source: &str, config: WorldConfig, steps: usize) -> Simulation {
+    let mut grid = Grid::new_from_string(source);
+    grid.config = config;
+    simulate_grid(grid, (0, 0), steps)
+}
+
+/// Runs `grid` for `steps` ticks with a single cursor starting at
+/// `start` heading right.
+pub(crate) fn simulate_grid(mut grid: Grid, start: (i64, i64), steps: usize) -> Simulation {
+    grid.apply(GridUpdate {
+        x: start.0,
+        y: start.1,
+        action: GridUpdateAction::SpawnCursor {
+            id: 0,
+            delta: Delta::RIGHT,
+            stack: vec![],
+            stack_stack: vec![],
+            storage_offset: (0, 0),
+            semantics: BTreeMap::new(),
+            energy: 1000,
+            string_mode: false,
+            priority: 0,
+        },
+    });
+    let mut simulation = Simulation::new(grid);
+    for _ in 0..steps {
+        simulation.step();
+    }
+    simulation
+}
+
+/// Like `simulate`, but returns the stack of the cursor.
+pub(crate) fn run(source: &str, steps: usize) -> Vec<i64> {
+    let simulation = simulate(source, steps);
+    simulation.grid.get_cursor(0).unwrap().stack.clone()
+}
+
+/// Like `simulate` in a world without bounds, but returns the delta of the
+/// cursor.
+pub(crate) fn delta_after(source: &str, steps: usize) -> Delta {
+    let config = WorldConfig {
+        bounds: None,
+        ..WorldConfig::default()
+    };
+    let simulation = simulate_with_config(source, config, steps);
+    simulation.grid.get_cursor(0).unwrap().delta
+}
+
+/// Source that pushes `i64::MAX` in 129 ticks, by doubling 1 until it
+/// overflows and subtracting 1.
+pub(crate) fn push_i64_max() -> String {
+    format!("1{}1-", ":+".repeat(63))
+}
+
+/// Steps `simulation` up to `steps` times or until the cursor is gone, and
+/// collects its output.
+pub(crate) fn collect_output(simulation: &mut Simulation, steps: usize) -> String {
+    let mut output = String::new();
+    for _ in 0..steps {
+        if simulation.grid.cursor_chunks.is_empty() {
+            break;
+        }
+        for update in simulation.step() {
+            if let GridUpdateAction::Output { text, .. } = update.action {
+                output.push_str(&text);
+            }
+        }
+    }
+    output
+}
+
+pub(crate) fn send_input(simulation: &mut Simulation, text: &str) {
+    let (x, y) = simulation.grid.get_cursor_position(0).unwrap();
+    simulation.grid.apply(GridUpdate {
+        x,
+        y,
+        action: GridUpdateAction::AppendInput {
+            id: 0,
+            text: text.to_string(),
+        },
+    });
+}
+
+/// A database that is deleted when it is dropped.
+pub(crate) fn temporary_db() -> sled::Db {
+    sled::Config::new().temporary(true).open().unwrap()
+}
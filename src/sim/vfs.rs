@@ -0,0 +1,220 @@
+use std::fmt::Display;
+
+/// Longest file name the filesystem accepts, in bytes
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Limits on how much a virtual filesystem can hold.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Quota {
+    pub file_size: usize,
+    pub total_size: usize,
+}
+
+impl Default for Quota {
+    fn default() -> Quota {
+        Quota {
+            file_size: 1 << 20,
+            total_size: 64 << 20,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VfsError {
+    InvalidName,
+    FileTooLarge,
+    QuotaExceeded,
+    Storage(sled::Error),
+}
+
+impl Display for VfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VfsError::InvalidName => write!(f, "invalid file name"),
+            VfsError::FileTooLarge => write!(f, "file is larger than the quota allows"),
+            VfsError::QuotaExceeded => write!(f, "filesystem is full"),
+            VfsError::Storage(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VfsError {}
+
+impl From<sled::Error> for VfsError {
+    fn from(e: sled::Error) -> VfsError {
+        VfsError::Storage(e)
+    }
+}
+
+/// Files that programs read with `i` and write with `o`, kept in a sled
+/// tree next to the world rather than on the host's disk. Names are plain
+/// keys, so there are no directories to escape from.
+#[derive(Clone)]
+pub struct Vfs {
+    files: sled::Tree,
+    quota: Quota,
+}
+
+impl Vfs {
+    pub fn open(db: &sled::Db, quota: Quota) -> Result<Vfs, VfsError> {
+        Ok(Vfs {
+            files: db.open_tree("files")?,
+            quota,
+        })
+    }
+
+    fn check_name(name: &str) -> Result<(), VfsError> {
+        if name.is_empty() || name.len() > MAX_NAME_LENGTH || name.chars().any(char::is_control) {
+            return Err(VfsError::InvalidName);
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>, VfsError> {
+        Vfs::check_name(name)?;
+        Ok(self.files.get(name)?.map(|data| data.to_vec()))
+    }
+
+    /// Creates or replaces a file, if that keeps the filesystem within its
+    /// quota.
+    pub fn write(&self, name: &str, data: &[u8]) -> Result<(), VfsError> {
+        self.check_write(name, data.len())?;
+        self.files.insert(name, data)?;
+        Ok(())
+    }
+
+    /// Checks that a file of `size` bytes could be written under `name`
+    /// without going over the quota.
+    pub fn check_write(&self, name: &str, size: usize) -> Result<(), VfsError> {
+        Vfs::check_name(name)?;
+        if size > self.quota.file_size {
+            return Err(VfsError::FileTooLarge);
+        }
+        let replaced = self.files.get(name)?.map_or(0, |old| old.len());
+        if self.used()? - replaced + size > self.quota.total_size {
+            return Err(VfsError::QuotaExceeded);
+        }
+        Ok(())
+    }
+
+    /// The number of bytes stored in all files.
+    pub fn used(&self) -> Result<usize, VfsError> {
+        let mut used = 0;
+        for entry in self.files.iter() {
+            let (_, data) = entry?;
+            used += data.len();
+        }
+        Ok(used)
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::step::Simulation;
+    use crate::sim::testing::{delta_after, push_i64_max, simulate, temporary_db};
+    use crate::sim::{Delta, GridUpdateAction};
+
+    fn simulate_with_vfs(source: &str, vfs: &Vfs, steps: usize) -> Simulation {
+        let mut simulation = simulate(source, 0);
+        simulation.vfs = Some(vfs.clone());
+        for _ in 0..steps {
+            simulation.step();
+        }
+        simulation
+    }
+
+    fn temporary_vfs(quota: Quota) -> Vfs {
+        Vfs::open(&temporary_db(), quota).unwrap()
+    }
+
+    #[test]
+    fn input_file_loads_into_grid() {
+        let vfs = temporary_vfs(Quota::default());
+        vfs.write("in.txt", b"ab\r\ncde").unwrap();
+        let simulation = simulate_with_vfs(r#"5100"txt.ni"i"#, &vfs, 13);
+        assert_eq!(simulation.grid.get_cell(5, 1), b'a' as i32);
+        assert_eq!(simulation.grid.get_cell(6, 1), b'b' as i32);
+        assert_eq!(simulation.grid.get_cell(7, 1), b' ' as i32);
+        assert_eq!(simulation.grid.get_cell(7, 2), b'e' as i32);
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().stack,
+            vec![3, 2, 5, 1]
+        );
+
+        // Missing files reflect
+        let simulation = simulate_with_vfs(r#"5100"txt.on"i"#, &vfs, 13);
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().delta, Delta::LEFT);
+    }
+
+    #[test]
+    fn output_file_saves_rectangle() {
+        let vfs = temporary_vfs(Quota::default());
+        // Text mode drops trailing spaces
+        simulate_with_vfs(r#"510010"tuo"o"#, &vfs, 12);
+        assert_eq!(vfs.read("out").unwrap().unwrap(), b"51001\n");
+        simulate_with_vfs(r#"820000"tuo"o"#, &vfs, 12);
+        assert_eq!(vfs.read("out").unwrap().unwrap(), b"820000\"t\n        \n");
+    }
+
+    #[test]
+    fn output_file_is_written_with_the_tick() {
+        let vfs = temporary_vfs(Quota::default());
+        let mut simulation = simulate_with_vfs(r#"510010"tuo"o"#, &vfs, 11);
+        assert_eq!(vfs.read("out").unwrap(), None);
+        let updates = simulation.step();
+        assert!(updates.iter().any(|update| update.action
+            == GridUpdateAction::WriteFile {
+                id: 0,
+                name: "out".to_string(),
+                data: b"51001\n".to_vec(),
+            }));
+        assert_eq!(vfs.read("out").unwrap().unwrap(), b"51001\n");
+    }
+
+    #[test]
+    fn file_coordinates_wrap() {
+        let vfs = temporary_vfs(Quota::default());
+        vfs.write("a", b"xy").unwrap();
+        let input = format!(r#"{}000"a"i"#, push_i64_max());
+        let simulation = simulate_with_vfs(&input, &vfs, 136);
+        // None of the file lands inside the world
+        assert_eq!(
+            simulation.grid.get_cursor(0).unwrap().stack,
+            vec![0, 0, i64::MAX, 0]
+        );
+        let output = format!(r#"11{}000"b"o"#, push_i64_max());
+        let simulation = simulate_with_vfs(&output, &vfs, 138);
+        assert!(simulation.grid.get_cursor(0).unwrap().stack.is_empty());
+        assert_eq!(vfs.read("b").unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn file_instructions_respect_quota() {
+        let vfs = temporary_vfs(Quota {
+            file_size: 4,
+            total_size: 8,
+        });
+        let simulation = simulate_with_vfs(r#"510010"tuo"o"#, &vfs, 12);
+        assert_eq!(simulation.grid.get_cursor(0).unwrap().delta, Delta::LEFT);
+        assert_eq!(vfs.read("out").unwrap(), None);
+
+        vfs.write("a", b"1234").unwrap();
+        vfs.write("b", b"1234").unwrap();
+        assert!(matches!(vfs.write("c", b"1"), Err(VfsError::QuotaExceeded)));
+        // Replacing a file only counts the difference
+        vfs.write("b", b"12").unwrap();
+        vfs.write("c", b"12").unwrap();
+        assert!(matches!(vfs.write("", b""), Err(VfsError::InvalidName)));
+    }
+
+    #[test]
+    fn file_instructions_reflect_without_vfs() {
+        assert_eq!(delta_after("i", 1), Delta::LEFT);
+        assert_eq!(delta_after("o", 1), Delta::LEFT);
+    }
+}